@@ -1,86 +1,567 @@
+use crate::libzuma::simulator::SLOW_FACTOR;
+use crate::libzuma::trajectory::{land_projectiles, trace_shot, Projectile};
 use crate::libzuma::*;
 use std::time::{Duration, Instant};
 
-#[derive(Clone, Copy)]
+#[macro_use]
+mod params;
+mod rollout;
+mod search;
+
+pub use params::{ParamSet, ParamSpec, Profile};
+
+// Forward speed of the chain in a typical level, per frame
+const NORMAL_SPEED: f32 = 0.5;
+
+params! {
+    // How every strategy aims and values shots
+    pub struct BotParams("common") {
+        // What the bots assume about the game, see `Physics`. They apply to the bot's copy
+        // of the state, so the chain is grouped, pushed and hit the way the bot expects
+        gap_threshold: f32 = GAP_THRESHOLD, 30.0..=40.0,
+        ball_spacing: f32 = BALL_SPACING, 30.0..=34.0,
+        shot_radius: f32 = BALL_SPACING, 20.0..=40.0,
+        frame_ms: f32 = FRAME_DURATION.as_millis() as f32, 10.0..=30.0,
+        // How far off the curve, towards the frog, shots are aimed
+        normal_offset: f32 = 15., 0.0..=32.0,
+        // What the effects and chain reactions are worth, in number of balls cleared
+        reverse_value: f32 = 20., 0.0..=60.0,
+        slow_value: f32 = 8., 0.0..=30.0,
+        chain_value: f32 = 3., 0.0..=10.0,
+        // What the second shot of a plan is worth compared to the first one,
+        // as the chain will have moved by the time it is shot
+        second_shot_factor: f32 = 0.8, 0.0..=1.0,
+        // What moving the frog elsewhere must gain, in number of balls cleared,
+        // since the chain keeps moving while the frog gets there
+        move_margin: f32 = 1., 0.0..=10.0,
+        // Frames before the chain reaches the skull under which it starts being a danger,
+        // and under which nothing matters more than pushing it back
+        danger_frames: f32 = 1200., 100.0..=3000.0,
+        emergency_frames: f32 = 300., 0.0..=1200.0,
+        // What hitting the chain right at its front is worth in an emergency,
+        // in number of balls cleared
+        front_value: f32 = 5., 0.0..=20.0,
+        // What pushing the chain away from the skull is worth to the planners, in number
+        // of balls cleared for going from no danger to reaching it
+        danger_value: f32 = 30., 0.0..=100.0,
+        // What a ball that clears nothing is worth to the planners for each ball of its color
+        // it ends up touching, less than any clear so that it is only a tie breaker
+        setup_value: f32 = 0.5, 0.0..=3.0,
+    }
+}
+
+impl BotParams {
+    pub fn physics(&self) -> Physics {
+        Physics {
+            ball_spacing: self.ball_spacing,
+            gap_threshold: self.gap_threshold,
+            shot_radius: self.shot_radius,
+            frame_duration: Duration::from_secs_f32(self.frame_ms / 1000.),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub enum BotMove {
     Nothing,
     Shoot(Point),
     SwapShoot(Point),
+    MoveFrog(Point), // Jump or slide to another position
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum BotMode {
-    ColorBot,
-    PalindromeBreaker,
+// A way of picking shots. Each bot is a strategy, made by the registry below,
+// and keeps whatever it needs from one shot to the next. The interface lets a copy
+// of it think on another thread
+pub trait Strategy: StrategyClone + Send {
+    // Used on the command line and to remember the strategy picked on each level
+    fn id(&self) -> &'static str;
+    // Shown in the interface
+    fn name(&self) -> &'static str;
+
+    fn config(&self) -> StrategyConfig {
+        StrategyConfig::default()
+    }
+
+    // The parameters shared by every strategy, also used by `suggest_shot`
+    fn params(&self) -> &BotParams;
+    fn params_mut(&mut self) -> &mut BotParams;
+
+    // Every parameter the strategy is tuned with, the common ones first
+    fn param_sets(&self) -> Vec<&dyn ParamSet> {
+        vec![self.params()]
+    }
+
+    fn param_sets_mut(&mut self) -> Vec<&mut dyn ParamSet> {
+        vec![self.params_mut()]
+    }
+
+    // The move to play now. Flying balls have already landed in `state`
+    fn suggest(&mut self, frog: &Frog, state: &GameState, budget: ThinkBudget) -> BotMove;
+
+    // A new level or game started, what was learned about the last one no longer applies
+    fn reset(&mut self) {}
+
+    // Anything worth showing about the last move
+    fn status(&self) -> Option<String> {
+        None
+    }
+}
+
+// Copies any strategy that is `Clone`, so that they don't each have to
+pub trait StrategyClone {
+    fn clone_box(&self) -> Box<dyn Strategy>;
 }
 
-impl BotMode {
-    pub const ALL: &[Self] = &[Self::ColorBot, Self::PalindromeBreaker];
+impl<T: Strategy + Clone + 'static> StrategyClone for T {
+    fn clone_box(&self) -> Box<dyn Strategy> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Strategy> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
 }
 
-impl std::fmt::Display for BotMode {
+impl std::fmt::Debug for dyn Strategy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            BotMode::ColorBot => "Color matcher",
-            BotMode::PalindromeBreaker => "Simple palindrome breaker",
+        self.id().fmt(f)
+    }
+}
+
+// What `suggest_shot` does before asking the strategy, which has to opt in to it.
+// The strategies that were there before these steps keep playing the way they did
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StrategyConfig {
+    pub emergency: bool, // Clear the front of the chain when it is about to reach the skull
+    pub move_frog: bool, // Jump or slide to where a better shot is
+}
+
+impl StrategyConfig {
+    // Every step
+    pub fn all() -> Self {
+        Self {
+            emergency: true,
+            move_frog: true,
         }
-        .fmt(f)
     }
 }
 
-pub struct Shot {
-    ball_id: u32,   // Id of the ball that was shot
-    target_id: u32, // Id of the target ball
-    shot_time: Instant,
-    expected_travel_time: Duration,
+// Every strategy, in the order they are listed. A new strategy only needs to be added here
+const STRATEGIES: &[fn() -> Box<dyn Strategy>] = &[
+    || Box::<ColorMatcher>::default(),
+    || Box::<PalindromeBreaker>::default(),
+    || Box::<TwoShotPlanner>::default(),
+    || Box::<search::SearchPlanner>::default(),
+    || Box::<rollout::MonteCarlo>::default(),
+];
+
+// An entry of the registry, to list the strategies and make new ones
+#[derive(Clone, Copy)]
+pub struct StrategyInfo {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub make: fn() -> Box<dyn Strategy>,
+}
+
+impl StrategyInfo {
+    pub fn all() -> Vec<Self> {
+        STRATEGIES
+            .iter()
+            .map(|&make| {
+                let strategy = make();
+                Self {
+                    id: strategy.id(),
+                    name: strategy.name(),
+                    make,
+                }
+            })
+            .collect()
+    }
+
+    pub fn find(id: &str) -> Option<Self> {
+        Self::all()
+            .into_iter()
+            .find(|info| info.id.eq_ignore_ascii_case(id))
+    }
+}
+
+impl PartialEq for StrategyInfo {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for StrategyInfo {}
+
+impl std::fmt::Debug for StrategyInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.id.fmt(f)
+    }
+}
+
+impl std::fmt::Display for StrategyInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.name.fmt(f)
+    }
+}
+
+#[derive(Clone, Default)]
+struct ColorMatcher {
+    params: BotParams,
+}
+
+impl Strategy for ColorMatcher {
+    fn id(&self) -> &'static str {
+        "ColorBot"
+    }
+
+    fn name(&self) -> &'static str {
+        "Color matcher"
+    }
+
+    fn suggest(&mut self, frog: &Frog, state: &GameState, _: ThinkBudget) -> BotMove {
+        suggest_shot_color(frog, state, &self.params)
+    }
+
+    fn params(&self) -> &BotParams {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut BotParams {
+        &mut self.params
+    }
+}
+
+#[derive(Clone, Default)]
+struct PalindromeBreaker {
+    params: BotParams,
+}
+
+impl Strategy for PalindromeBreaker {
+    fn id(&self) -> &'static str {
+        "PalindromeBreaker"
+    }
+
+    fn name(&self) -> &'static str {
+        "Simple palindrome breaker"
+    }
+
+    fn suggest(&mut self, frog: &Frog, state: &GameState, _: ThinkBudget) -> BotMove {
+        suggest_shot_palidrome_simple(frog, state, &self.params)
+    }
+
+    fn params(&self) -> &BotParams {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut BotParams {
+        &mut self.params
+    }
+}
+
+#[derive(Clone, Default)]
+struct TwoShotPlanner {
+    params: BotParams,
+}
+
+impl Strategy for TwoShotPlanner {
+    fn id(&self) -> &'static str {
+        "TwoShotPlanner"
+    }
+
+    fn name(&self) -> &'static str {
+        "Two-shot planner (with swaps)"
+    }
+
+    fn config(&self) -> StrategyConfig {
+        StrategyConfig::all()
+    }
+
+    fn suggest(&mut self, frog: &Frog, state: &GameState, _: ThinkBudget) -> BotMove {
+        suggest_shot_two_shots(frog, state, &self.params)
+    }
+
+    fn params(&self) -> &BotParams {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut BotParams {
+        &mut self.params
+    }
+}
+
+// How long a bot may think about a shot. The bots that keep improving with more time
+// stop at the deadline or after the given number of rollouts, whichever comes first.
+// The clock starts when the budget is made, so that everything done for the shot counts
+#[derive(Clone, Copy, Debug)]
+pub struct ThinkBudget {
+    pub deadline: Option<Instant>, // None for no deadline
+    pub rollouts: usize,
+}
+
+impl ThinkBudget {
+    // To fit between two shots in the game
+    pub fn time(time: Duration) -> Self {
+        Self {
+            deadline: Instant::now().checked_add(time),
+            rollouts: usize::MAX,
+        }
+    }
+
+    // So that a game plays the same way every time, whatever the machine
+    pub fn rollouts(rollouts: usize) -> Self {
+        Self {
+            deadline: None,
+            rollouts,
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
 }
 
 pub fn suggest_shot(
     frog: &Frog,
     state: &GameState,
-    mode: BotMode,
-    memo: &mut Vec<Shot>,
+    strategy: &mut dyn Strategy,
+    budget: ThinkBudget,
 ) -> BotMove {
-    match mode {
-        BotMode::ColorBot => suggest_shot_color(frog, state, memo),
-        BotMode::PalindromeBreaker => suggest_shot_palidrome_simple(frog, state, memo),
+    let params = *strategy.params();
+    let mut state = state.clone();
+    for stream in &mut state.streams {
+        stream.physics = params.physics();
     }
+    // Balls that are already flying will be part of the chain by the time the next one lands
+    let state = &land_projectiles(&state);
+
+    // A moving frog is aimed from where it is heading, which is where it is by the time
+    // the ball leaves. It is already going somewhere, so it isn't sent elsewhere
+    let moving = frog.is_moving();
+    let frog = &Frog {
+        location: frog.destination,
+        ..*frog
+    };
+    let config = strategy.config();
+    // Close to the skull, there is no time to move or to set up shots
+    if config.emergency {
+        if let Some(shot) = suggest_emergency_shot(frog, state, &params) {
+            return shot;
+        }
+    }
+    if config.move_frog && !moving {
+        if let Some(position) = better_position(frog, state, &params) {
+            return BotMove::MoveFrog(position);
+        }
+    }
+
+    strategy.suggest(frog, state, budget)
 }
 
-pub fn reachable_balls(frog: &Frog, balls: &GameState) -> Vec<Ball> {
-    let mut reachable_balls = vec![];
+// A ball the bot shot, remembered until it shows up in the chain or should have landed
+#[derive(Clone, Copy, Debug)]
+struct Shot {
+    projectile: Projectile, // As it left the frog
+    shot_time: Duration,    // Game time at which the ball was shot
+    expected_travel_time: Duration,
+}
+
+// The shots of the bot that haven't landed yet. They stand in for the balls in flight
+// when those can't be read from the game, so that the same group isn't shot twice
+#[derive(Clone, Default)]
+pub struct ShotMemo {
+    shots: Vec<Shot>,
+}
+
+impl ShotMemo {
+    // Remember the ball the move shoots, if it shoots one that hits the chain
+    pub fn remember(
+        &mut self,
+        frog: &Frog,
+        state: &GameState,
+        bot_move: BotMove,
+        physics: Physics,
+    ) {
+        let (ball, aim) = match bot_move {
+            BotMove::Shoot(aim) => (frog.active_ball, aim),
+            BotMove::SwapShoot(aim) => (frog.next_ball, aim),
+            BotMove::MoveFrog(_) | BotMove::Nothing => return,
+        };
+        let projectile = Projectile::new(frog, ball, aim);
+        let Some(impact) = projectile.first_impact(state, f32::MAX) else {
+            return;
+        };
+
+        self.shots.push(Shot {
+            projectile,
+            shot_time: state.time,
+            expected_travel_time: physics.frame_duration.mul_f32(impact.travel_time),
+        });
+    }
+
+    // Where the remembered balls are now. Those that landed are forgotten
+    pub fn in_flight(&mut self, state: &GameState, physics: Physics) -> Vec<Projectile> {
+        self.shots.retain(|shot| {
+            state.time.saturating_sub(shot.shot_time) < shot.expected_travel_time
+                && state.find_ball(shot.projectile.ball.id).is_none()
+        });
+
+        self.shots
+            .iter()
+            .map(|shot| {
+                let frames = state.time.saturating_sub(shot.shot_time).as_secs_f32()
+                    / physics.frame_duration.as_secs_f32();
+                let mut projectile = shot.projectile;
+                projectile.position =
+                    projectile.position + frames * projectile.speed * projectile.direction;
+                projectile
+            })
+            .collect()
+    }
 
-    let non_tunnel_balls: Vec<_> = balls
+    pub fn clear(&mut self) {
+        self.shots.clear();
+    }
+}
+
+// Another position the frog can move to, from which it can clear noticeably more
+fn better_position(frog: &Frog, state: &GameState, params: &BotParams) -> Option<Point> {
+    let positions = state.level.as_ref()?.frog_type.positions();
+    if positions.len() < 2 {
+        return None;
+    }
+
+    let value_at = |location| {
+        let frog = Frog {
+            location,
+            destination: location,
+            ..*frog
+        };
+        best_plan(&frog, state, params).map_or(0., |(value, _, _)| value)
+    };
+    let current_value = value_at(frog.location);
+    positions
+        .into_iter()
+        .filter(|position| position.dist(&frog.location) > FROG_POSITION_TOLERANCE)
+        .map(|position| (value_at(position), position))
+        .filter(|&(value, _)| value > current_value + params.move_margin)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, position)| position)
+}
+
+// How far the front of the chain is along its curve, from 0 at the start to 1 at the skull
+pub fn stream_progress(stream: &BallStream) -> f32 {
+    match stream.balls.last() {
+        Some(ball) if stream.curve.length() > 0. => {
+            (ball.distance_along_path / stream.curve.length()).clamp(0., 1.)
+        }
+        _ => 0.,
+    }
+}
+
+// Frames before the front of the chain reaches the skull if nothing gets cleared,
+// counting the time it still goes back, stays stopped or goes slower.
+// Only the back of the chain moves forward, so the gaps must close before the front moves
+pub fn frames_to_skull(stream: &BallStream) -> f32 {
+    let Some(front) = stream.balls.last() else {
+        return f32::INFINITY;
+    };
+    if front.distance_along_path >= stream.curve.length() {
+        return 0.;
+    }
+    let gaps: f32 = stream
         .balls
-        .iter()
-        .filter(|ball| !balls.curve.get_tunnel_at_dist(ball.distance_along_path))
-        .collect();
+        .windows(2)
+        .map(|pair| {
+            let spacing = stream.physics.ball_spacing;
+            (pair[1].distance_along_path - pair[0].distance_along_path - spacing).max(0.)
+        })
+        .sum();
+    let mut left = stream.curve.length() - front.distance_along_path + gaps;
+    if left <= 0. {
+        return 0.;
+    }
 
-    for &ball_src in &non_tunnel_balls {
-        let mut ball_has_line_of_sight = true;
-        for ball_obstacle in &non_tunnel_balls {
-            if &ball_src == ball_obstacle {
-                continue;
-            }
+    // The effects run at the same time, and going back wins over stopping, which wins over slowing
+    let backwards = stream.backwards_time_left as f32;
+    let stopped = (stream.stopped_time_left as f32).max(backwards);
+    let slowed = (stream.slowed_time_left as f32).max(stopped);
+    left -= stream.back_speed * backwards;
+    let slow_speed = stream.forward_speed * SLOW_FACTOR;
+    if slow_speed * (slowed - stopped) >= left {
+        return stopped + left / slow_speed;
+    }
+    left -= slow_speed * (slowed - stopped);
 
-            let src_vec = ball_src.coordinates - frog.location;
-            let obstacle_vec = ball_obstacle.coordinates - frog.location;
+    if stream.forward_speed <= 0. {
+        return f32::INFINITY;
+    }
+    slowed + left / stream.forward_speed
+}
 
-            let k_leeway = 27. / src_vec.dot(&obstacle_vec).abs().sqrt();
+// How close the chain is to reaching the skull, from 0 when it has plenty of time
+// to 1 when it is there
+pub fn danger(stream: &BallStream, params: &BotParams) -> f32 {
+    (1. - frames_to_skull(stream) / params.danger_frames).clamp(0., 1.)
+}
 
-            let k = src_vec.dot(&obstacle_vec) / src_vec.dot(&src_vec);
-            let projected_point = frog.location + k * src_vec;
+fn is_emergency(stream: &BallStream, params: &BotParams) -> bool {
+    frames_to_skull(stream) < params.emergency_frames
+}
 
-            let dist_sq_circle_to_line = projected_point.dist_sq(&ball_obstacle.coordinates);
-            let radius_sq = 32_f32.powi(2);
-            if dist_sq_circle_to_line < radius_sq && k_leeway < k && k < 1. - k_leeway {
-                ball_has_line_of_sight = false;
-                break;
-            }
+// What firing `effect` is worth for the given stream, in number of balls cleared
+pub fn effect_value(effect: BallEffect, stream: &BallStream, params: &BotParams) -> f32 {
+    match effect {
+        // Sending the chain back matters most when it is about to reach the skull
+        BallEffect::Reverse if stream.backwards_time_left == 0 => {
+            params.reverse_value * stream_progress(stream).powi(2).max(danger(stream, params))
+        }
+        BallEffect::Slow if stream.slowed_time_left == 0 => {
+            params.slow_value * (stream.forward_speed / NORMAL_SPEED).min(3.)
         }
+        // The balls destroyed by a bomb are already counted in what it clears
+        _ => 0.,
+    }
+}
 
-        if ball_has_line_of_sight {
-            reachable_balls.push(*ball_src);
+// What a chain reaction is worth: the balls it clears, how long it goes on
+// and the effects it fires
+pub fn cascade_value(stream: &BallStream, cascade: &Cascade, params: &BotParams) -> f32 {
+    let effects: f32 = cascade
+        .effects
+        .iter()
+        .map(|&effect| effect_value(effect, stream, params))
+        .sum();
+
+    cascade.popped() as f32 + params.chain_value * cascade.depth() as f32 + effects
+}
+
+// What inserting `ball` next to the ball at `target_idx` of the given stream is worth
+pub fn score_shot(
+    state: &GameState,
+    stream_idx: usize,
+    target_idx: usize,
+    side: InsertSide,
+    ball: Ball,
+    params: &BotParams,
+) -> f32 {
+    let stream = &state.streams[stream_idx];
+    cascade_value(stream, &stream.predict_shot(target_idx, side, ball), params)
+}
+
+// Balls next to which a shot aimed at their center would land
+pub fn reachable_balls(frog: &Frog, state: &GameState) -> Vec<Ball> {
+    let mut reachable_balls = vec![];
+    for (stream_idx, stream) in state.streams.iter().enumerate() {
+        for (i, ball) in stream.balls.iter().enumerate() {
+            if trace_shot(frog, state, ball.coordinates)
+                .is_some_and(|impact| impact.lands_next_to(stream_idx, i))
+            {
+                reachable_balls.push(*ball);
+            }
         }
     }
 
@@ -89,6 +570,7 @@ pub fn reachable_balls(frog: &Frog, balls: &GameState) -> Vec<Ball> {
 
 #[derive(Debug)]
 struct Palindrome {
+    stream: usize,
     center: usize,
     sequence: Vec<(BallColor, u32)>,
 }
@@ -114,10 +596,19 @@ impl Palindrome {
     }
 }
 
-fn find_palidromes(balls: &GameState) -> Vec<Palindrome> {
+fn find_palidromes(state: &GameState) -> Vec<Palindrome> {
+    let mut palindromes = vec![];
+    for (stream_idx, stream) in state.streams.iter().enumerate() {
+        palindromes.extend(find_stream_palidromes(stream_idx, stream));
+    }
+
+    palindromes
+}
+
+fn find_stream_palidromes(stream_idx: usize, stream: &BallStream) -> Vec<Palindrome> {
     // Transform the ball sequence into a [(color, count, ball_idx)]
     let mut rle_balls = vec![];
-    for (i, ball) in balls.balls.iter().enumerate() {
+    for (i, ball) in stream.balls.iter().enumerate() {
         match rle_balls.last() {
             Some(&(color, count, _)) if color == ball.color => {
                 rle_balls.last_mut().unwrap().1 = count + 1
@@ -133,7 +624,8 @@ fn find_palidromes(balls: &GameState) -> Vec<Palindrome> {
         let mut sequence = vec![(color, count)];
 
         loop {
-            match (rle_balls.get(i - radius), rle_balls.get(i + radius)) {
+            let before = i.checked_sub(radius).and_then(|j| rle_balls.get(j));
+            match (before, rle_balls.get(i + radius)) {
                 (Some(&before), Some(&after)) if before.0 == after.0 => {
                     sequence.push((before.0, before.1 + after.1))
                 }
@@ -143,6 +635,7 @@ fn find_palidromes(balls: &GameState) -> Vec<Palindrome> {
         }
 
         palindromes.push(Palindrome {
+            stream: stream_idx,
             center: ball_idx,
             sequence,
         });
@@ -156,27 +649,33 @@ fn find_palidromes(balls: &GameState) -> Vec<Palindrome> {
 // (to make aim better in situations where the track isn't perfectly perpandicular to the frog)
 pub fn adjust_for_travel_time(
     frog: &Frog,
-    state: &GameState,
+    state: &BallStream,
     target_idx: usize,
-    memo: &[Shot],
-) -> (Point, Duration) {
+    params: &BotParams,
+) -> Point {
     let target_ball = &state.balls[target_idx];
+    if state.curve.points.len() < 2 {
+        return target_ball.coordinates;
+    }
 
     // Compute travel time
     let dist = frog.location.dist(&target_ball.coordinates);
     let travel_time = dist / frog.ball_exit_speed;
 
     // Determine the start and end indices of the ball group
-    let mut comparison = &state.balls[target_idx].coordinates;
+    let is_gap = |a: &Ball, b: &Ball| {
+        (a.distance_along_path - b.distance_along_path).abs() > params.gap_threshold
+    };
+    let mut comparison = &state.balls[target_idx];
     let gap_before_start = state.balls[..target_idx].iter().rposition(|ball| {
-        let res = comparison.dist_sq(&ball.coordinates) > 32.5_f32.powi(2);
-        comparison = &ball.coordinates;
+        let res = is_gap(comparison, ball);
+        comparison = ball;
         res
     });
-    let mut comparison = &state.balls[target_idx].coordinates;
+    let mut comparison = &state.balls[target_idx];
     let gap_before_end = state.balls[target_idx..].iter().position(|ball| {
-        let res = comparison.dist_sq(&ball.coordinates) > 32.5_f32.powi(2);
-        comparison = &ball.coordinates;
+        let res = is_gap(comparison, ball);
+        comparison = ball;
         res
     });
     let ball_speed = match (
@@ -189,107 +688,119 @@ pub fn adjust_for_travel_time(
         _ => 0.0,
     };
 
-    // Compute number of balls that will be inserted
-    let mut inserted_balls = 0;
-    for ball in &state.balls[gap_before_start.unwrap_or(0)..target_idx] {
-        if memo.iter().any(|shot| shot.target_id == ball.id) {
-            inserted_balls += 1;
-        }
-    }
-
-    // Compute ball distance within that time
-    let ball_distance = target_ball.distance_along_path
-        + ball_speed * travel_time
-        + (inserted_balls as f32) * 32.1;
+    // Compute ball distance within that time. The ball moves while the shot flies,
+    // so the time is computed again to where it will be
+    let mut ball_distance = target_ball.distance_along_path + ball_speed * travel_time;
+    let travel_time = frog
+        .location
+        .dist(&state.curve.get_pos_at_dist(ball_distance))
+        / frog.ball_exit_speed;
+    ball_distance = target_ball.distance_along_path + ball_speed * travel_time;
 
     let point = state.curve.get_pos_at_dist(ball_distance);
 
-    let mut normal = state.curve.get_normal_at_dist(ball_distance).unit();
+    let mut normal = state.curve.get_normal_at_dist(ball_distance);
     if normal.dot(&(point - frog.location)) < 0. {
-        normal *= -15.;
+        normal *= -params.normal_offset;
     } else {
-        normal *= 15.;
+        normal *= params.normal_offset;
     }
 
-    (point - normal, Duration::from_millis((travel_time * 17.) as u64))
+    point - normal
 }
 
-pub fn suggest_shot_color(frog: &Frog, state: &GameState, memo: &mut Vec<Shot>) -> BotMove {
-    if state.balls.is_empty() {
+pub fn suggest_shot_color(frog: &Frog, state: &GameState, params: &BotParams) -> BotMove {
+    if state.balls().next().is_none() {
         return BotMove::Nothing;
     }
 
-    // Update memo:
-    // If the id of the ball that was shot matches one of the balls, remove it
-    // If the ball was shot too long ago, remove it
-    for ball in &state.balls {
-        memo.retain(|shot| {
-            shot.ball_id != ball.id && shot.shot_time.elapsed() < shot.expected_travel_time
-        });
-    }
-
-    // Transform the ball sequence into a [(color, count, ball_idx)]
+    // Transform the ball sequences into a [(color, count, ball_idx, stream_idx)]
     let mut rle_balls = vec![];
-    for (i, ball) in state.balls.iter().rev().enumerate() {
-        let i = state.balls.len() - 1 - i;
-        match rle_balls.last() {
-            Some(&(color, count, _)) if color == ball.color => {
-                rle_balls.last_mut().unwrap().1 = count + 1;
-                rle_balls.last_mut().unwrap().2 = i;
+    for (stream_idx, stream) in state.streams.iter().enumerate() {
+        let stream_start = rle_balls.len();
+        for (i, ball) in stream.balls.iter().rev().enumerate() {
+            let i = stream.balls.len() - 1 - i;
+            match rle_balls[stream_start..].last() {
+                Some(&(color, count, _, _)) if color == ball.color => {
+                    rle_balls.last_mut().unwrap().1 = count + 1;
+                    rle_balls.last_mut().unwrap().2 = i;
+                }
+                _ => rle_balls.push((ball.color, 1, i, stream_idx)),
             }
-            _ => rle_balls.push((ball.color, 1, i)),
         }
     }
     rle_balls.sort_by_key(|k| -(k.1 as i32));
     let reachable_balls = reachable_balls(frog, state);
+    if reachable_balls.is_empty() {
+        return BotMove::Nothing;
+    }
 
+    // Among the groups of the right color, the biggest one wins unless popping
+    // another one is worth more, e.g. because of the effects it fires
     let mut ball_to_shoot = None;
-    for (color, count, idx) in rle_balls {
+    let mut best_score = f32::MIN;
+    for (color, count, idx, stream_idx) in rle_balls {
         if color == frog.active_ball.color {
-            let ball_group = &state.balls[idx..idx + count];
+            let ball_group = &state.streams[stream_idx].balls[idx..idx + count];
             let lowest_reachable_pos = reachable_balls
                 .iter()
                 .position(|ball| ball_group.contains(ball));
             if let Some(best_pos) = lowest_reachable_pos {
-                ball_to_shoot = Some(best_pos);
-                break;
+                let score = score_shot(
+                    state,
+                    stream_idx,
+                    idx,
+                    InsertSide::Start,
+                    frog.active_ball,
+                    params,
+                );
+                if score > best_score {
+                    ball_to_shoot = Some(best_pos);
+                    best_score = score;
+                }
             }
         }
     }
 
     let ball_to_shoot = ball_to_shoot.unwrap_or(reachable_balls.len() - 1);
-    // Transform the index into an index of state.balls
-    let ball_to_shoot = state
-        .balls
-        .iter()
-        .position(|&ball| ball == reachable_balls[ball_to_shoot])
-        .unwrap();
-
-    let (target_point, travel_time) = adjust_for_travel_time(frog, state, ball_to_shoot, memo);
-
-    // Add ball to memo
-    memo.push(Shot {
-        ball_id: frog.active_ball.id,
-        target_id: state.balls[ball_to_shoot].id,
-        shot_time: Instant::now(),
-        expected_travel_time: travel_time,
-    });
+    // Transform the index into an index of its stream
+    let (stream_idx, ball_to_shoot) = state.find_ball(reachable_balls[ball_to_shoot].id).unwrap();
+    let stream = &state.streams[stream_idx];
 
-    BotMove::Shoot(target_point)
+    BotMove::Shoot(adjust_for_travel_time(frog, stream, ball_to_shoot, params))
 }
 
-pub fn suggest_shot_palidrome_simple(frog: &Frog, state: &GameState, memo: &mut [Shot]) -> BotMove {
-    if state.balls.len() < 4 {
+pub fn suggest_shot_palidrome_simple(
+    frog: &Frog,
+    state: &GameState,
+    params: &BotParams,
+) -> BotMove {
+    if state.balls().count() < 4 {
         return BotMove::Nothing;
     }
 
     let reachable_balls = reachable_balls(frog, state);
-    let mut palindromes = find_palidromes(state);
-    palindromes.sort_by_key(|k| -(k.get_breaking_len() as i32));
+    if reachable_balls.is_empty() {
+        return BotMove::Nothing;
+    }
+    let mut palindromes: Vec<_> = find_palidromes(state)
+        .into_iter()
+        .map(|palindrome| {
+            // Effects in the center group fire as soon as it pops
+            let stream = &state.streams[palindrome.stream];
+            let effects: f32 = stream.balls[palindrome.center..]
+                .iter()
+                .take(palindrome.sequence[0].1 as usize)
+                .map(|ball| effect_value(ball.effect, stream, params))
+                .sum();
+            (palindrome.get_breaking_len() + effects, palindrome)
+        })
+        .collect();
+    palindromes.sort_by(|a, b| b.0.total_cmp(&a.0));
 
     let mut target = None;
-    for palindrome in palindromes {
-        let palindrome_center = state.balls[palindrome.center];
+    for (_, palindrome) in palindromes {
+        let palindrome_center = state.streams[palindrome.stream].balls[palindrome.center];
         if palindrome_center.color == frog.active_ball.color
             && reachable_balls.contains(&palindrome_center)
         {
@@ -299,8 +810,357 @@ pub fn suggest_shot_palidrome_simple(frog: &Frog, state: &GameState, memo: &mut
     }
 
     let target = target.unwrap_or(reachable_balls[reachable_balls.len() - 1]);
-    // Transform the index into an index of state.balls
-    let ball_to_shoot = state.balls.iter().position(|&ball| ball == target).unwrap();
+    // Transform the index into an index of its stream
+    let (stream_idx, ball_to_shoot) = state.find_ball(target.id).unwrap();
+
+    let stream = &state.streams[stream_idx];
+
+    BotMove::Shoot(adjust_for_travel_time(frog, stream, ball_to_shoot, params))
+}
+
+// A place the frog can send a ball to
+#[derive(Clone, Copy, Debug)]
+struct Candidate {
+    stream_idx: usize,
+    aim_idx: usize,   // Index of the ball to aim at
+    target_id: u32,   // Id of the ball that gets hit
+    side: InsertSide, // Side of the hit ball on which the shot ball ends up
+}
+
+// When a chain is about to reach the skull, clear what is closest to its front,
+// preferring whatever also fires a Reverse ball. Shots that clear nothing would only
+// make the chain longer, so without any, the bot plays as usual
+fn suggest_emergency_shot(frog: &Frog, state: &GameState, params: &BotParams) -> Option<BotMove> {
+    let (stream_idx, stream) = state
+        .streams
+        .iter()
+        .enumerate()
+        .filter(|(_, stream)| is_emergency(stream, params))
+        .min_by(|a, b| frames_to_skull(a.1).total_cmp(&frames_to_skull(b.1)))?;
+    let front = stream.balls.last()?.distance_along_path.max(1.);
+
+    let orders = [(false, frog.active_ball), (true, frog.next_ball)];
+    let mut best: Option<(f32, usize, bool)> = None;
+    for candidate in candidate_shots(frog, state) {
+        if candidate.stream_idx != stream_idx {
+            continue;
+        }
+        let Some(target_idx) = stream
+            .balls
+            .iter()
+            .position(|b| b.id == candidate.target_id)
+        else {
+            continue;
+        };
+
+        for (swap, ball) in orders {
+            if swap && ball.color == frog.active_ball.color {
+                continue;
+            }
+            let cascade = stream.predict_shot(target_idx, candidate.side, ball);
+            if cascade.popped() == 0 {
+                continue;
+            }
+            let closeness = stream.balls[target_idx].distance_along_path / front;
+            let value = cascade_value(stream, &cascade, params) + params.front_value * closeness;
+            if best.is_none_or(|(best_value, _, _)| value > best_value) {
+                best = Some((value, candidate.aim_idx, swap));
+            }
+        }
+    }
+
+    let (_, aim_idx, swap) = best?;
+    let target_point = adjust_for_travel_time(frog, stream, aim_idx, params);
+    Some(if swap {
+        BotMove::SwapShoot(target_point)
+    } else {
+        BotMove::Shoot(target_point)
+    })
+}
+
+// Every distinct place the frog can send a ball to by aiming at the center of a ball
+fn candidate_shots(frog: &Frog, state: &GameState) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = vec![];
+    for (stream_idx, stream) in state.streams.iter().enumerate() {
+        for (i, ball) in stream.balls.iter().enumerate() {
+            let Some(impact) = trace_shot(frog, state, ball.coordinates) else {
+                continue;
+            };
+            if !impact.lands_next_to(stream_idx, i) {
+                continue;
+            }
+
+            let target_id = stream.balls[impact.ball_idx].id;
+            if !candidates
+                .iter()
+                .any(|c| c.target_id == target_id && c.side == impact.side)
+            {
+                candidates.push(Candidate {
+                    stream_idx,
+                    aim_idx: i,
+                    target_id,
+                    side: impact.side,
+                });
+            }
+        }
+    }
+
+    candidates
+}
+
+// True if inserting a ball of `color` next to the ball at `idx` can clear anything
+fn can_pop(stream: &BallStream, idx: usize, side: InsertSide, color: BallColor) -> bool {
+    let neighbor = match side {
+        InsertSide::Start => idx.checked_sub(1),
+        InsertSide::End => Some(idx + 1),
+    };
+
+    stream.balls[idx].color == color
+        || neighbor
+            .and_then(|i| stream.balls.get(i))
+            .is_some_and(|ball| ball.color == color)
+}
+
+// What shooting `first_ball` at `first`, then `second_ball` at the best place left, is worth.
+// The chain is assumed not to move between the two shots
+fn plan_value(
+    state: &GameState,
+    candidates: &[Candidate],
+    first: Candidate,
+    first_ball: Ball,
+    second_ball: Ball,
+    params: &BotParams,
+) -> f32 {
+    let stream = &state.streams[first.stream_idx];
+    let Some(target_idx) = stream.balls.iter().position(|b| b.id == first.target_id) else {
+        return 0.;
+    };
+    let mut after = stream.clone();
+    let idx = after.insert_ball_at(target_idx, first.side, first_ball);
+    let first_value = cascade_value(stream, &after.clear_cascade_at(idx), params);
+
+    let second_value = candidates
+        .iter()
+        .filter_map(|candidate| {
+            let stream = if candidate.stream_idx == first.stream_idx {
+                &after
+            } else {
+                &state.streams[candidate.stream_idx]
+            };
+            // The target may have been cleared by the first shot
+            let idx = stream
+                .balls
+                .iter()
+                .position(|ball| ball.id == candidate.target_id)?;
+            if !can_pop(stream, idx, candidate.side, second_ball.color) {
+                return None;
+            }
+
+            let cascade = stream.predict_shot(idx, candidate.side, second_ball);
+            Some(cascade_value(stream, &cascade, params))
+        })
+        .fold(0., f32::max);
+
+    first_value + params.second_shot_factor * second_value
+}
+
+// The best two shots with the active and next balls, in either order:
+// what they are worth, where the first one goes and whether to swap the balls first
+fn best_plan(frog: &Frog, state: &GameState, params: &BotParams) -> Option<(f32, Candidate, bool)> {
+    let candidates = candidate_shots(frog, state);
+
+    let orders = [
+        (false, frog.active_ball, frog.next_ball),
+        (true, frog.next_ball, frog.active_ball),
+    ];
+    let mut best: Option<(f32, Candidate, bool)> = None;
+    for (swap, first_ball, second_ball) in orders {
+        // Swapping two balls of the same color changes nothing
+        if swap && first_ball.color == second_ball.color {
+            continue;
+        }
+
+        for &candidate in &candidates {
+            let value = plan_value(
+                state,
+                &candidates,
+                candidate,
+                first_ball,
+                second_ball,
+                params,
+            );
+            if best.is_none_or(|(best_value, _, _)| value > best_value) {
+                best = Some((value, candidate, swap));
+            }
+        }
+    }
+
+    best
+}
+
+// Plan the next two shots with the active and next balls, in either order,
+// swapping them first when shooting the next ball first is worth more
+pub fn suggest_shot_two_shots(frog: &Frog, state: &GameState, params: &BotParams) -> BotMove {
+    let Some((value, candidate, swap)) = best_plan(frog, state, params) else {
+        return BotMove::Nothing;
+    };
+
+    // Nothing can be cleared within two shots, fall back to matching colors
+    if value <= 0. {
+        return suggest_shot_color(frog, state, params);
+    }
+
+    let stream = &state.streams[candidate.stream_idx];
+    let target_point = adjust_for_travel_time(frog, stream, candidate.aim_idx, params);
+    if swap {
+        BotMove::SwapShoot(target_point)
+    } else {
+        BotMove::Shoot(target_point)
+    }
+}
+
+#[test]
+fn effects_are_valued() {
+    use crate::libzuma::simulator::{loose_ball, straight_state};
+    use BallColor::*;
+    let mut state = straight_state(&[Blue, Blue, Red, Red], 800., NORMAL_SPEED);
+    state.streams[0].balls[3].effect = BallEffect::Reverse;
+
+    let params = BotParams::default();
+    // Close to the skull, the reverse ball makes the red group worth more
+    // than the blue one although they are the same size
+    let shot = |target_idx, color| {
+        let ball = loose_ball(4, color);
+        score_shot(&state, 0, target_idx, InsertSide::Start, ball, &params)
+    };
+    let blue = shot(0, Blue);
+    let red = shot(2, Red);
+    assert_eq!(3., blue);
+    assert!(red > blue + 10.);
+
+    // Slowing down the chain is worth more when it is fast
+    let mut fast = state.streams[0].clone();
+    fast.forward_speed = 2. * NORMAL_SPEED;
+    assert!(
+        effect_value(BallEffect::Slow, &fast, &params)
+            > effect_value(BallEffect::Slow, &state.streams[0], &params)
+    );
+}
+
+#[test]
+fn swaps_when_the_next_ball_is_better() {
+    use crate::libzuma::simulator::{loose_ball, straight_state};
+    use BallColor::*;
+    let state = straight_state(&[Blue, Red, Red, Blue, Green], 200., 0.);
+    let mut frog = Frog {
+        location: Point { x: 264., y: 300. },
+        destination: Point { x: 264., y: 300. },
+        active_ball: loose_ball(10, Yellow),
+        next_ball: loose_ball(11, Red),
+        ball_exit_speed: 10.,
+    };
+
+    let bot_move = suggest_shot_two_shots(&frog, &state, &BotParams::default());
+    assert!(matches!(bot_move, BotMove::SwapShoot(_)));
+
+    // Nothing can be cleared with two yellow balls, the color matcher decides
+    frog.next_ball = loose_ball(11, Yellow);
+    let bot_move = suggest_shot_two_shots(&frog, &state, &BotParams::default());
+    assert!(matches!(bot_move, BotMove::Shoot(_)));
+}
+
+#[test]
+fn clears_the_front_in_an_emergency() {
+    use crate::libzuma::simulator::{loose_ball, straight_stream};
+    use BallColor::*;
+    let colors = [Green, Blue, Blue, Yellow, Green, Red, Red];
+    let stream = straight_stream(&colors, 700., NORMAL_SPEED);
+
+    // The front is 108 units away, which the chain covers in 216 frames,
+    // more when it is slowed or stopped first
+    assert_eq!(216., frames_to_skull(&stream));
+    let mut held_back = stream.clone();
+    held_back.stopped_time_left = 50;
+    held_back.slowed_time_left = 100;
+    assert_eq!(
+        50. + 50. + (108. - 12.5) / NORMAL_SPEED,
+        frames_to_skull(&held_back)
+    );
+    let params = BotParams::default();
+    assert!(danger(&stream, &params) > danger(&held_back, &params));
+
+    let state = GameState {
+        streams: vec![stream],
+        ..GameState::new()
+    };
+    let frog = Frog {
+        location: Point { x: 800., y: 300. },
+        destination: Point { x: 800., y: 300. },
+        active_ball: loose_ball(10, Blue),
+        next_ball: loose_ball(11, Red),
+        ball_exit_speed: 10.,
+    };
+
+    // Both pairs clear as much, but the red one is right at the front
+    let BotMove::SwapShoot(aim) = suggest_shot(
+        &frog,
+        &state,
+        &mut TwoShotPlanner::default(),
+        ThinkBudget::rollouts(0),
+    ) else {
+        panic!("Expected a swap to shoot the red ball");
+    };
+    assert!(aim.x > 840.);
+    // The strategies from before the emergency step keep playing without it
+    assert!(!ColorMatcher::default().config().emergency);
+}
+
+#[test]
+fn remembered_shots_fly_until_they_land() {
+    use crate::libzuma::simulator::{loose_ball, straight_state};
+    use BallColor::*;
+    let mut state = straight_state(&[Blue, Red, Red, Blue], 200., 0.);
+    let frog = Frog {
+        location: Point { x: 264., y: 300. },
+        destination: Point { x: 264., y: 300. },
+        active_ball: loose_ball(10, Red),
+        next_ball: loose_ball(11, Green),
+        ball_exit_speed: 10.,
+    };
+
+    let physics = Physics::default();
+    let mut memo = ShotMemo::default();
+    memo.remember(
+        &frog,
+        &state,
+        BotMove::Shoot(Point { x: 264., y: 0. }),
+        physics,
+    );
+    memo.remember(
+        &frog,
+        &state,
+        BotMove::MoveFrog(Point { x: 0., y: 0. }),
+        physics,
+    );
+    assert_eq!(1, memo.in_flight(&state, physics).len());
+
+    // The ball has flown 10 frames, and lands next to the reds
+    state.time = 10 * FRAME_DURATION;
+    let in_flight = memo.in_flight(&state, physics);
+    assert_eq!(Point { x: 264., y: 200. }, in_flight[0].position);
+    let landed = land_projectiles(&GameState {
+        projectiles: in_flight,
+        ..state.clone()
+    });
+    assert_eq!(
+        vec![Blue, Blue],
+        landed.balls().map(|ball| ball.color).collect::<Vec<_>>()
+    );
 
-    BotMove::Shoot(adjust_for_travel_time(frog, state, ball_to_shoot, memo).0)
+    // It is forgotten once it shows up in the chain, or once it should have landed
+    let mut arrived = state.clone();
+    arrived.streams[0].balls[0].id = 10;
+    assert!(memo.clone().in_flight(&arrived, physics).is_empty());
+    state.time = 30 * FRAME_DURATION;
+    assert!(memo.in_flight(&state, physics).is_empty());
 }
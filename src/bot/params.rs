@@ -0,0 +1,251 @@
+use super::Strategy;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// Parameters the bots are tuned with. Each strategy keeps its own copy of them so that
+// they can be changed while it plays, and profiles save them to disk to reuse them later
+
+// A parameter, and the values it may take
+#[derive(Clone, Copy, Debug)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    pub min: f32,
+    pub max: f32,
+    pub integer: bool, // Only takes whole values
+}
+
+// A group of parameters that can be read and changed one by one, by index in `specs`
+pub trait ParamSet {
+    // Name of the group in profiles
+    fn section(&self) -> &'static str;
+    fn specs(&self) -> &'static [ParamSpec];
+    fn get(&self, idx: usize) -> f32;
+    // Values outside of the range of the parameter are clamped to it
+    fn set(&mut self, idx: usize, value: f32);
+}
+
+// Types parameters can have, all edited as f32
+pub trait ParamValue: Copy {
+    const INTEGER: bool;
+    fn to_f32(self) -> f32;
+    fn from_f32(value: f32) -> Self;
+}
+
+impl ParamValue for f32 {
+    const INTEGER: bool = false;
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+}
+
+impl ParamValue for usize {
+    const INTEGER: bool = true;
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value.round().max(0.) as usize
+    }
+}
+
+// Define a struct of parameters with their default value and range:
+// `name: type = default, min..=max,` for each of them
+macro_rules! params {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident($section:literal) {
+            $($field:ident: $ty:ty = $default:expr, $min:literal..=$max:literal,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        pub struct $name {
+            $(pub $field: $ty,)*
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                Self {
+                    $($field: $default,)*
+                }
+            }
+        }
+
+        impl $crate::bot::params::ParamSet for $name {
+            fn section(&self) -> &'static str {
+                $section
+            }
+
+            fn specs(&self) -> &'static [$crate::bot::params::ParamSpec] {
+                use $crate::bot::params::{ParamSpec, ParamValue};
+                &[$(ParamSpec {
+                    name: stringify!($field),
+                    min: $min as f32,
+                    max: $max as f32,
+                    integer: <$ty as ParamValue>::INTEGER,
+                },)*]
+            }
+
+            fn get(&self, idx: usize) -> f32 {
+                use $crate::bot::params::ParamValue;
+                [$(self.$field.to_f32(),)*][idx]
+            }
+
+            fn set(&mut self, idx: usize, value: f32) {
+                use $crate::bot::params::ParamValue;
+                let spec = self.specs()[idx];
+                let value = value.clamp(spec.min, spec.max);
+                let mut fields = 0..;
+                $(if fields.next() == Some(idx) {
+                    self.$field = <$ty as ParamValue>::from_f32(value);
+                })*
+            }
+        }
+    };
+}
+
+// Parameters of every strategy, saved to a text file with a section per strategy:
+//
+//     [SearchPlanner]
+//     common.gap_threshold = 32.5
+//     search.depth = 3
+//
+// Parameters missing from a profile keep their value, so that profiles still load
+// after parameters are added
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    // By strategy id, then by <section>.<name>
+    strategies: BTreeMap<String, BTreeMap<String, f32>>,
+}
+
+impl Profile {
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_string())
+    }
+
+    pub fn parse(text: &str) -> std::io::Result<Self> {
+        let invalid = |line_idx: usize, line: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid line {}: {:?}", line_idx + 1, line),
+            )
+        };
+
+        let mut profile = Self::default();
+        let mut strategy = None;
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(id) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                strategy = Some(profile.strategies.entry(id.trim().to_string()).or_default());
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(line_idx, line))?;
+            let value = value.trim().parse().map_err(|_| invalid(line_idx, line))?;
+            strategy
+                .as_mut()
+                .ok_or_else(|| invalid(line_idx, line))?
+                .insert(key.trim().to_string(), value);
+        }
+
+        Ok(profile)
+    }
+
+    // Keep the current parameters of a strategy
+    pub fn store(&mut self, strategy: &dyn Strategy) {
+        let values = self
+            .strategies
+            .entry(strategy.id().to_string())
+            .or_default();
+        for set in strategy.param_sets() {
+            for (idx, spec) in set.specs().iter().enumerate() {
+                values.insert(format!("{}.{}", set.section(), spec.name), set.get(idx));
+            }
+        }
+    }
+
+    // Give a strategy the parameters kept for it
+    pub fn apply(&self, strategy: &mut dyn Strategy) {
+        let Some(values) = self.strategies.get(strategy.id()) else {
+            return;
+        };
+        for set in strategy.param_sets_mut() {
+            for (idx, spec) in set.specs().iter().enumerate() {
+                if let Some(&value) = values.get(&format!("{}.{}", set.section(), spec.name)) {
+                    set.set(idx, value);
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (id, values) in &self.strategies {
+            writeln!(f, "[{}]", id)?;
+            for (key, value) in values {
+                writeln!(f, "{} = {}", key, value)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn profiles_round_trip() {
+    use super::{BotParams, StrategyInfo};
+    let search = StrategyInfo::find("SearchPlanner").unwrap();
+    let mut tuned = (search.make)();
+    tuned.params_mut().gap_threshold = 35.;
+    // Out of range values are clamped, and whole values rounded
+    let mut sets = tuned.param_sets_mut();
+    sets[0].set(0, 100.);
+    sets[1].set(0, 2.6);
+    assert_eq!(40., tuned.params().gap_threshold);
+    assert_eq!(3., tuned.param_sets()[1].get(0));
+
+    let mut profile = Profile::default();
+    profile.store(tuned.as_ref());
+    let text = profile.to_string();
+    assert!(text.starts_with("[SearchPlanner]\ncommon."));
+    let loaded = Profile::parse(&text).unwrap();
+    assert_eq!(profile, loaded);
+
+    let mut fresh = (search.make)();
+    loaded.apply(fresh.as_mut());
+    assert_eq!(tuned.params(), fresh.params());
+    assert_eq!(tuned.param_sets()[1].get(0), fresh.param_sets()[1].get(0));
+
+    // Other strategies and missing parameters keep their values
+    let mut color = (StrategyInfo::find("ColorBot").unwrap().make)();
+    Profile::parse("[ColorBot]\ncommon.chain_value = 4\n")
+        .unwrap()
+        .apply(color.as_mut());
+    loaded.apply(color.as_mut());
+    assert_eq!(4., color.params().chain_value);
+    assert_eq!(
+        BotParams::default().gap_threshold,
+        color.params().gap_threshold
+    );
+
+    assert!(Profile::parse("common.chain_value = 4").is_err());
+    assert!(Profile::parse("[ColorBot]\ncommon.chain_value = four").is_err());
+}
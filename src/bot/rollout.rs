@@ -0,0 +1,277 @@
+use super::search::{shots, Shot};
+use super::*;
+use crate::libzuma::simulator::Rng;
+
+params! {
+    pub struct RolloutParams("rollout") {
+        // Shots played by a rollout after the one being evaluated
+        depth: usize = 3, 1..=8,
+        // First shots worth rolling out, the best ones by what they are worth right away
+        options: usize = 12, 1..=30,
+        // How much the rollouts keep trying first shots that did badly so far
+        exploration: f32 = 2., 0.0..=10.0,
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MonteCarlo {
+    params: BotParams,
+    rollout: RolloutParams,
+    rollouts: usize, // Done for the last shot
+}
+
+impl Strategy for MonteCarlo {
+    fn id(&self) -> &'static str {
+        "MonteCarlo"
+    }
+
+    fn name(&self) -> &'static str {
+        "Monte Carlo rollouts"
+    }
+
+    fn config(&self) -> StrategyConfig {
+        StrategyConfig::all()
+    }
+
+    fn suggest(&mut self, frog: &Frog, state: &GameState, budget: ThinkBudget) -> BotMove {
+        let (bot_move, rollouts) =
+            suggest_shot_rollouts(frog, state, budget, &self.params, &self.rollout);
+        self.rollouts = rollouts;
+        bot_move
+    }
+
+    fn params(&self) -> &BotParams {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut BotParams {
+        &mut self.params
+    }
+
+    fn param_sets(&self) -> Vec<&dyn ParamSet> {
+        vec![&self.params, &self.rollout]
+    }
+
+    fn param_sets_mut(&mut self) -> Vec<&mut dyn ParamSet> {
+        vec![&mut self.params, &mut self.rollout]
+    }
+
+    fn status(&self) -> Option<String> {
+        Some(format!("Rollouts for the last shot: {}", self.rollouts))
+    }
+}
+
+// A first shot, and what the rollouts after it were worth
+struct Choice {
+    shot: Shot,
+    value: f32,       // Of the shot itself
+    state: GameState, // Once it has landed
+    held: Ball,       // The ball left in the frog
+    total: f32,       // Of every rollout
+    rollouts: usize,
+}
+
+impl Choice {
+    fn mean(&self) -> f32 {
+        self.value + self.total / self.rollouts.max(1) as f32
+    }
+}
+
+// Try every shot of the active ball or the swapped one, then play the next few shots
+// many times over with random balls to see what each one leads to. The frog only ever
+// gets colors that are still in the chain, so the random balls are picked among those.
+// Also returns how many rollouts were done
+pub fn suggest_shot_rollouts(
+    frog: &Frog,
+    state: &GameState,
+    budget: ThinkBudget,
+    params: &BotParams,
+    rollout_params: &RolloutParams,
+) -> (BotMove, usize) {
+    let orders = [
+        (false, frog.active_ball, frog.next_ball),
+        (true, frog.next_ball, frog.active_ball),
+    ];
+    let mut choices = vec![];
+    for (swap, ball, held) in orders {
+        if swap && ball.color == held.color {
+            continue;
+        }
+        for (shot, value, state) in shots(frog, state, ball, params) {
+            choices.push(Choice {
+                shot: Shot { swap, ..shot },
+                value,
+                state,
+                held,
+                total: 0.,
+                rollouts: 0,
+            });
+        }
+    }
+    choices.sort_by(|a, b| b.value.total_cmp(&a.value));
+    choices.truncate(rollout_params.options);
+
+    // The same state always gets the same random balls
+    let seed = state.balls().fold(0u64, |seed, ball| {
+        seed.wrapping_mul(31).wrapping_add(ball.id as u64)
+    });
+    let mut rng = Rng::new(seed);
+    let mut done = 0;
+    while done < budget.rollouts && !budget.is_over() {
+        // Roll out the choice that looks best, or that was tried the least
+        let explore = |choice: &Choice| match choice.rollouts {
+            0 => f32::INFINITY,
+            n => {
+                let exploration = ((done as f32).ln() / n as f32).sqrt();
+                choice.mean() + rollout_params.exploration * exploration
+            }
+        };
+        let Some(choice) = choices
+            .iter_mut()
+            .max_by(|a, b| explore(a).total_cmp(&explore(b)))
+        else {
+            break;
+        };
+
+        choice.total += rollout(&choice.state, choice.held, &mut rng, params, rollout_params);
+        choice.rollouts += 1;
+        done += 1;
+    }
+
+    let Some(choice) = choices.iter().max_by(|a, b| a.mean().total_cmp(&b.mean())) else {
+        return (suggest_shot_color(frog, state, params), done);
+    };
+    let stream = &state.streams[choice.shot.stream_idx];
+    let target_point = adjust_for_travel_time(frog, stream, choice.shot.aim_idx, params);
+    let bot_move = if choice.shot.swap {
+        BotMove::SwapShoot(target_point)
+    } else {
+        BotMove::Shoot(target_point)
+    };
+    (bot_move, done)
+}
+
+// What the next shots are worth, starting with `held` and then random balls.
+// To be fast, every shot goes next to the group it clears best, without checking
+// that the frog can actually reach it
+fn rollout(
+    state: &GameState,
+    held: Ball,
+    rng: &mut Rng,
+    params: &BotParams,
+    rollout_params: &RolloutParams,
+) -> f32 {
+    let mut state = state.clone();
+    let mut held = held;
+    let Some(mut next) = random_ball(&state, held, rng) else {
+        return 0.;
+    };
+
+    let mut total = 0.;
+    let mut factor = params.second_shot_factor;
+    for _ in 0..rollout_params.depth {
+        // Shoot whichever of the two balls clears more, like a swap would.
+        // When neither clears anything, the active ball goes somewhere it does no harm
+        let shoot_held = best_clear(&state, held, params);
+        let shoot_next = best_clear(&state, next, params);
+        let (target, swap) = match (shoot_held, shoot_next) {
+            (Some(a), Some(b)) if b.2 > a.2 => (Some(b), true),
+            (None, Some(b)) => (Some(b), true),
+            (a, _) => (a, false),
+        };
+        let ball = if swap { next } else { held };
+        if let Some((stream_idx, idx, value)) = target {
+            let stream = &mut state.streams[stream_idx];
+            let idx = stream.insert_ball_at(idx, InsertSide::Start, ball);
+            stream.clear_cascade_at(idx);
+            total += factor * value;
+        }
+        factor *= params.second_shot_factor;
+
+        // The ball that wasn't shot stays in the frog
+        if !swap {
+            held = next;
+        }
+        let Some(ball) = random_ball(&state, held, rng) else {
+            break;
+        };
+        next = ball;
+    }
+
+    total
+}
+
+// The reachable group that `ball` clears best, as its stream and first ball,
+// and what clearing it is worth
+fn best_clear(state: &GameState, ball: Ball, params: &BotParams) -> Option<(usize, usize, f32)> {
+    let mut best: Option<(usize, usize, f32)> = None;
+    for (stream_idx, stream) in state.streams.iter().enumerate() {
+        for (idx, target) in stream.balls.iter().enumerate() {
+            let group_start = idx == 0 || stream.balls[idx - 1].color != target.color;
+            if target.color != ball.color || !target.is_reachable || !group_start {
+                continue;
+            }
+
+            let cascade = stream.predict_shot(idx, InsertSide::Start, ball);
+            let value = cascade_value(stream, &cascade, params);
+            if best.is_none_or(|(_, _, best_value)| value > best_value) {
+                best = Some((stream_idx, idx, value));
+            }
+        }
+    }
+
+    best
+}
+
+// A ball of a color still in the chain, like the game deals them
+fn random_ball(state: &GameState, like: Ball, rng: &mut Rng) -> Option<Ball> {
+    let mut colors: Vec<BallColor> = vec![];
+    for ball in state.balls() {
+        if !colors.contains(&ball.color) {
+            colors.push(ball.color);
+        }
+    }
+    if colors.is_empty() {
+        return None;
+    }
+
+    Some(Ball {
+        color: colors[rng.below(colors.len())],
+        effect: BallEffect::None,
+        ..like
+    })
+}
+
+#[test]
+fn rollouts_stay_within_budget() {
+    use crate::libzuma::simulator::{loose_ball, straight_state};
+    use BallColor::*;
+    let colors = [
+        Blue, Red, Red, Green, Yellow, Yellow, Blue, Green, Green, Red,
+    ];
+    let state = straight_state(&colors, 100., 0.);
+    let frog = Frog {
+        location: Point { x: 300., y: 300. },
+        destination: Point { x: 300., y: 300. },
+        active_ball: loose_ball(20, Yellow),
+        next_ball: loose_ball(21, Red),
+        ball_exit_speed: 10.,
+    };
+
+    // The random balls only depend on the state, so the same state gets the same shot
+    let (params, rollout_params) = (BotParams::default(), RolloutParams::default());
+    let aim =
+        |budget| match suggest_shot_rollouts(&frog, &state, budget, &params, &rollout_params).0 {
+            BotMove::Shoot(aim) => Some((false, aim)),
+            BotMove::SwapShoot(aim) => Some((true, aim)),
+            _ => None,
+        };
+    let shot = aim(ThinkBudget::rollouts(100));
+    assert!(shot.is_some());
+    assert_eq!(shot, aim(ThinkBudget::rollouts(100)));
+
+    // How long it actually takes depends on the machine, so only the shot is checked
+    assert!(aim(ThinkBudget::time(Duration::from_millis(20))).is_some());
+    // Without any time, the shots are picked by what they are worth right away
+    assert!(aim(ThinkBudget::time(Duration::ZERO)).is_some());
+}
@@ -0,0 +1,244 @@
+use super::*;
+
+params! {
+    pub struct SearchParams("search") {
+        // Shots the search plans ahead. The frog only holds two balls, the shots after them
+        // are estimated from the colors left in the chain
+        depth: usize = 3, 1..=5,
+        // Plans kept after each shot to look further from
+        beam_width: usize = 6, 1..=20,
+    }
+}
+
+// The first shot of a plan, which is the one played
+#[derive(Clone, Copy, Debug)]
+pub struct Shot {
+    pub stream_idx: usize,
+    pub aim_idx: usize,
+    pub swap: bool,
+}
+
+#[derive(Clone)]
+struct Plan {
+    state: GameState, // Once the shots of the plan have landed
+    held: Vec<Ball>,  // Balls the frog shoots next, in order
+    value: f32,
+    first: Option<Shot>,
+}
+
+#[derive(Clone, Default)]
+pub struct SearchPlanner {
+    params: BotParams,
+    search: SearchParams,
+}
+
+impl Strategy for SearchPlanner {
+    fn id(&self) -> &'static str {
+        "SearchPlanner"
+    }
+
+    fn name(&self) -> &'static str {
+        "Search planner (beam search)"
+    }
+
+    fn config(&self) -> StrategyConfig {
+        StrategyConfig::all()
+    }
+
+    fn suggest(&mut self, frog: &Frog, state: &GameState, _: ThinkBudget) -> BotMove {
+        suggest_shot_search(frog, state, &self.params, &self.search)
+    }
+
+    fn params(&self) -> &BotParams {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut BotParams {
+        &mut self.params
+    }
+
+    fn param_sets(&self) -> Vec<&dyn ParamSet> {
+        vec![&self.params, &self.search]
+    }
+
+    fn param_sets_mut(&mut self) -> Vec<&mut dyn ParamSet> {
+        vec![&mut self.params, &mut self.search]
+    }
+}
+
+// Try every place the active ball or the swapped one can land, simulate what each clears,
+// and keep the best plans to look at the shots after them
+pub fn suggest_shot_search(
+    frog: &Frog,
+    state: &GameState,
+    params: &BotParams,
+    search: &SearchParams,
+) -> BotMove {
+    let mut beam = vec![Plan {
+        state: state.clone(),
+        held: vec![frog.active_ball, frog.next_ball],
+        value: 0.,
+        first: None,
+    }];
+
+    // Later shots are worth less, as the chain will have moved by the time they are shot
+    let mut factor = 1.;
+    for _ in 0..search.depth {
+        let mut next: Vec<_> = beam
+            .iter()
+            .flat_map(|plan| expand(frog, plan, factor, params))
+            .collect();
+        if next.is_empty() {
+            break;
+        }
+        next.sort_by(|a, b| b.value.total_cmp(&a.value));
+        next.truncate(search.beam_width);
+        beam = next;
+        factor *= params.second_shot_factor;
+    }
+
+    let Some(shot) = beam.first().and_then(|plan| plan.first) else {
+        return suggest_shot_color(frog, state, params);
+    };
+    let stream = &state.streams[shot.stream_idx];
+    let target_point = adjust_for_travel_time(frog, stream, shot.aim_idx, params);
+    if shot.swap {
+        BotMove::SwapShoot(target_point)
+    } else {
+        BotMove::Shoot(target_point)
+    }
+}
+
+// Plans that continue `plan` with one more shot
+fn expand(frog: &Frog, plan: &Plan, factor: f32, params: &BotParams) -> Vec<Plan> {
+    // The next ball is unknown, the plan gets what a ball of each color left could clear
+    if plan.held.is_empty() {
+        let mut colors: Vec<BallColor> = vec![];
+        for ball in plan.state.balls() {
+            if !colors.contains(&ball.color) {
+                colors.push(ball.color);
+            }
+        }
+        if colors.is_empty() {
+            return vec![];
+        }
+
+        let total: f32 = colors
+            .iter()
+            .map(|&color| {
+                let ball = Ball {
+                    color,
+                    effect: BallEffect::None,
+                    ..frog.active_ball
+                };
+                shots(frog, &plan.state, ball, params)
+                    .map(|(_, value, _)| value)
+                    .fold(0., f32::max)
+            })
+            .sum();
+        return vec![Plan {
+            held: vec![],
+            value: plan.value + factor * total / colors.len() as f32,
+            ..plan.clone()
+        }];
+    }
+
+    // The frog can shoot the first ball it holds, or swap and shoot the second one
+    let mut orders = vec![(false, plan.held[0], plan.held[1..].to_vec())];
+    if plan.held.len() >= 2 && plan.held[0].color != plan.held[1].color {
+        let mut rest = plan.held.clone();
+        let ball = rest.remove(1);
+        orders.push((true, ball, rest));
+    }
+
+    let mut plans = vec![];
+    for (swap, ball, rest) in orders {
+        for (shot, value, state) in shots(frog, &plan.state, ball, params) {
+            plans.push(Plan {
+                state,
+                held: rest.clone(),
+                value: plan.value + factor * value,
+                first: plan.first.or(Some(Shot { swap, ..shot })),
+            });
+        }
+    }
+
+    plans
+}
+
+// Every shot of `ball` from the frog, with what it is worth and the state once it has landed
+pub fn shots<'a>(
+    frog: &'a Frog,
+    state: &'a GameState,
+    ball: Ball,
+    params: &'a BotParams,
+) -> impl Iterator<Item = (Shot, f32, GameState)> + 'a {
+    candidate_shots(frog, state)
+        .into_iter()
+        .filter_map(move |candidate| {
+            let stream = &state.streams[candidate.stream_idx];
+            let target_idx = stream
+                .balls
+                .iter()
+                .position(|b| b.id == candidate.target_id)?;
+
+            let mut after = state.clone();
+            let landed = &mut after.streams[candidate.stream_idx];
+            let idx = landed.insert_ball_at(target_idx, candidate.side, ball);
+            let cascade = landed.clear_cascade_at(idx);
+            let pushed_back = danger(stream, params) - danger(landed, params);
+            let mut value =
+                cascade_value(stream, &cascade, params) + params.danger_value * pushed_back;
+            // Build up groups for the shots after
+            if cascade.popped() == 0 {
+                value += params.setup_value * (landed.group_at(idx).len() - 1) as f32;
+            }
+
+            let shot = Shot {
+                stream_idx: candidate.stream_idx,
+                aim_idx: candidate.aim_idx,
+                swap: false,
+            };
+            Some((shot, value, after))
+        })
+}
+
+#[test]
+fn search_finds_chain_reactions() {
+    use crate::libzuma::simulator::{loose_ball, straight_state};
+    use BallColor::*;
+    let colors = [Blue, Green, Yellow, Yellow, Red, Red, Yellow, Green, Green];
+    let state = straight_state(&colors, 200., 0.);
+    let frog = Frog {
+        location: Point { x: 344., y: 300. },
+        destination: Point { x: 344., y: 300. },
+        active_ball: loose_ball(10, Yellow),
+        next_ball: loose_ball(11, Red),
+        ball_exit_speed: 10.,
+    };
+
+    // Shooting the yellow ball clears three balls, but the red one sets off
+    // the yellows and then the greens, leaving only the blue ball
+    let root = Plan {
+        state: state.clone(),
+        held: vec![frog.active_ball, frog.next_ball],
+        value: 0.,
+        first: None,
+    };
+    let params = BotParams::default();
+    let best = expand(&frog, &root, 1., &params)
+        .into_iter()
+        .max_by(|a, b| a.value.total_cmp(&b.value))
+        .unwrap();
+    assert!(best.value >= 9. + 2. * params.chain_value);
+    assert_eq!(1, best.state.streams[0].balls.len());
+
+    let BotMove::SwapShoot(aim) =
+        suggest_shot_search(&frog, &state, &params, &SearchParams::default())
+    else {
+        panic!("Expected a swap to shoot the red ball");
+    };
+    // Aimed at the reds, or at the yellow right before them
+    let reds = 200. + 3. * BALL_SPACING..=200. + 5. * BALL_SPACING;
+    assert!(reds.contains(&aim.x));
+}
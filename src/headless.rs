@@ -0,0 +1,557 @@
+use crate::bot::{self, BotMove, Profile, Strategy, StrategyInfo, ThinkBudget};
+use crate::libzuma::simulator::{Rng, Simulator, Spawner};
+use crate::libzuma::trajectory::Projectile;
+use crate::libzuma::*;
+use std::sync::Arc;
+
+const USAGE: &str = "Usage: zum-ai-steam simulate <curve.dat>... [options]
+
+Every curve file gets its own stream of balls.
+
+Options:
+    --mode <name|all>      Bot to run, can be repeated (default: all)
+    --games <n>            Number of games per bot (default: 100)
+    --seed <n>             Seed of the first game (default: 0)
+    --balls <n>            Balls spawned on each curve over a level (default: 100)
+    --initial-balls <n>    Balls already on the curve when the level starts (default: 30)
+    --colors <n>           Number of different colors, 1 to 6 (default: 4)
+    --speed <f>            Forward speed of the chain, per frame (default: 0.5)
+    --frog <x,y>           Position of the frog (default: 320,240)
+    --jump <x,y>           Another position the frog can jump to, can be repeated
+    --slide <x,y>          Other end of a slider the frog moves along, starting at --frog
+    --exit-speed <f>       Speed of the shot balls, per frame (default: 10)
+    --shoot-every <ms>     Time between two shots (default: 250)
+    --max-frames <n>       Frames before a game is abandoned (default: 36000)
+    --rollouts <n>         Games rolled out per shot by the Monte Carlo bot (default: 200)
+    --profile <path>       Parameters of the bots, as saved by the interface (default: none)
+    --verbose              Print the result of every game";
+
+const CURVE_USAGE: &str = "Usage: zum-ai-steam curve <out.dat> <x,y> <x,y>...
+
+Write a curve file going through the given points in straight lines,
+to simulate games on it.";
+
+const FRAME_MS: u32 = FRAME_DURATION.as_millis() as u32;
+// How far the frog goes per frame when it jumps or slides
+const FROG_SPEED: f32 = 8.;
+
+#[derive(Clone, Debug)]
+pub struct LevelConfig {
+    pub name: String, // Taken from the curve files
+    pub curves: Vec<Curve>,
+    pub balls: u32, // Per curve
+    pub initial_balls: u32,
+    pub colors: Vec<BallColor>,
+    pub forward_speed: f32,
+    pub frog_location: Point,
+    pub frog_type: FrogType,
+    pub ball_exit_speed: f32,
+    pub shoot_every_frames: u32,
+    pub max_frames: u32,
+    pub rollouts: usize, // Per shot, for the bots that roll out games
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameResult {
+    Won,
+    Lost,
+    TimedOut,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GameReport {
+    pub result: GameResult,
+    pub balls_cleared: u32,
+    pub shots_fired: u32,
+    pub combos: u32, // Shots that cleared balls right after another shot did
+    pub chains: u32, // Groups that cleared because two segments rolled back together
+    pub moves: u32,  // Times the frog jumped or slid elsewhere
+    pub frames: u32, // Played, the one the game ended on included
+}
+
+// Options shared by the commands that play simulated games
+pub struct Options {
+    pub paths: Vec<String>, // Given before the options
+    pub modes: Vec<StrategyInfo>,
+    pub games: Option<u64>, // Each command has its own default
+    pub seed: u64,
+    pub verbose: bool,
+    pub profile: Profile,
+    pub level: LevelConfig, // Without its curves
+}
+
+// Parse the paths and options of a command. `extra` gets the options that are
+// specific to the command, and returns false for those it doesn't know either
+pub fn parse_options(
+    args: &[String],
+    usage: &str,
+    mut extra: impl FnMut(&str, &str) -> Result<bool, String>,
+) -> Result<Options, String> {
+    let mut args = args.iter().peekable();
+    let mut paths = vec![];
+    while let Some(path) = args.next_if(|arg| !arg.starts_with("--")) {
+        paths.push(path.clone());
+    }
+    if paths.is_empty() {
+        return Err(usage.into());
+    }
+
+    let mut options = Options {
+        paths,
+        modes: vec![],
+        games: None,
+        seed: 0,
+        verbose: false,
+        profile: Profile::default(),
+        level: LevelConfig {
+            name: String::new(),
+            curves: vec![],
+            balls: 100,
+            initial_balls: 30,
+            colors: BallColor::ALL[..4].to_vec(),
+            forward_speed: 0.5,
+            frog_location: Point { x: 320., y: 240. },
+            frog_type: FrogType::Static(Point { x: 320., y: 240. }),
+            ball_exit_speed: 10.,
+            shoot_every_frames: 250 / FRAME_MS,
+            max_frames: 36000,
+            rollouts: 200,
+        },
+    };
+    let level = &mut options.level;
+    let mut jumps = vec![];
+    let mut slide = None;
+
+    while let Some(arg) = args.next() {
+        if arg == "--verbose" {
+            options.verbose = true;
+            continue;
+        }
+
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}\n\n{}", arg, usage))?;
+        match arg.as_str() {
+            "--mode" if value == "all" => options.modes.extend(StrategyInfo::all()),
+            "--mode" => options.modes.push(parse_mode(value)?),
+            "--games" => options.games = Some(parse(arg, value)?),
+            "--seed" => options.seed = parse(arg, value)?,
+            "--balls" => level.balls = parse(arg, value)?,
+            "--initial-balls" => level.initial_balls = parse(arg, value)?,
+            "--colors" => {
+                let count: usize = parse(arg, value)?;
+                if !(1..=BallColor::ALL.len()).contains(&count) {
+                    return Err(format!(
+                        "--colors must be between 1 and {}",
+                        BallColor::ALL.len()
+                    ));
+                }
+                level.colors = BallColor::ALL[..count].to_vec();
+            }
+            "--speed" => level.forward_speed = parse(arg, value)?,
+            "--frog" => level.frog_location = parse_point(arg, value)?,
+            "--jump" => jumps.push(parse_point(arg, value)?),
+            "--slide" => slide = Some(parse_point(arg, value)?),
+            "--exit-speed" => level.ball_exit_speed = parse(arg, value)?,
+            "--shoot-every" => {
+                level.shoot_every_frames = (parse::<u32>(arg, value)? / FRAME_MS).max(1)
+            }
+            "--max-frames" => level.max_frames = parse(arg, value)?,
+            "--rollouts" => level.rollouts = parse(arg, value)?,
+            "--profile" => {
+                options.profile = Profile::load(value)
+                    .map_err(|err| format!("Could not read profile {}: {}", value, err))?
+            }
+            _ if extra(arg, value)? => {}
+            _ => return Err(format!("Unknown option {}\n\n{}", arg, usage)),
+        }
+    }
+
+    level.frog_type = match slide {
+        Some(end) => FrogType::Slider(level.frog_location, end),
+        None if !jumps.is_empty() => FrogType::Jumper([vec![level.frog_location], jumps].concat()),
+        None => FrogType::Static(level.frog_location),
+    };
+
+    Ok(options)
+}
+
+// The level of `template` played on the given curves, each with its own stream of balls
+pub fn load_level(template: &LevelConfig, curve_paths: &[String]) -> Result<LevelConfig, String> {
+    let mut level = template.clone();
+    let names: Vec<_> = curve_paths
+        .iter()
+        .map(|path| {
+            let path = std::path::Path::new(path.as_str());
+            path.file_stem()
+                .unwrap_or(path.as_os_str())
+                .to_string_lossy()
+        })
+        .collect();
+    level.name = names.join("+");
+    for curve_path in curve_paths {
+        let mut curve = Curve::new();
+        curve
+            .read_from_file(curve_path.clone())
+            .map_err(|err| format!("Could not read curve file {}: {}", curve_path, err))?;
+        level.curves.push(curve);
+    }
+
+    Ok(level)
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut options = parse_options(args, USAGE, |_, _| Ok(false))?;
+    if options.modes.is_empty() {
+        options.modes.extend(StrategyInfo::all());
+    }
+    let level = load_level(&options.level, &options.paths)?;
+
+    for mode in options.modes {
+        let reports: Vec<_> = (0..options.games.unwrap_or(100))
+            .map(|i| {
+                let seed = options.seed.wrapping_add(i);
+                let mut strategy = (mode.make)();
+                options.profile.apply(strategy.as_mut());
+                let report = play_game(&level, strategy.as_mut(), seed);
+                if options.verbose {
+                    println!("{} seed {}: {:?}", mode, seed, report);
+                }
+                report
+            })
+            .collect();
+        print_summary(&level.name, mode, &reports);
+    }
+
+    Ok(())
+}
+
+// Play a whole simulated game of the level with the given bot
+pub fn play_game(level: &LevelConfig, strategy: &mut dyn Strategy, seed: u64) -> GameReport {
+    strategy.reset();
+    let mut rng = Rng::derive(seed, "deal", 0);
+    let streams = level
+        .curves
+        .iter()
+        .map(|curve| BallStream {
+            curve: curve.clone(),
+            forward_speed: level.forward_speed,
+            ..BallStream::new()
+        })
+        .collect();
+    let mut sim = Simulator::new(GameState {
+        streams,
+        level: Some(Arc::new(Level {
+            id: level.name.clone(),
+            display_name: level.name.clone(),
+            frog_position: level.frog_location,
+            frog_type: level.frog_type.clone(),
+            background: String::new(),
+            curves: level.curves.clone(),
+        })),
+        ..GameState::new()
+    });
+    for i in 0..level.curves.len() {
+        sim.spawners.push(Spawner {
+            remaining: level.balls,
+            colors: level.colors.clone(),
+            rng: Rng::derive(seed, "spawn", i as u64),
+        });
+        sim.fill(i, level.initial_balls);
+    }
+
+    let active_ball = deal_ball(&mut sim, &mut rng, &level.colors);
+    let next_ball = deal_ball(&mut sim, &mut rng, &level.colors);
+    let mut frog = Frog {
+        location: level.frog_location,
+        destination: level.frog_location,
+        active_ball,
+        next_ball,
+        ball_exit_speed: level.ball_exit_speed,
+    };
+
+    let mut report = GameReport {
+        result: GameResult::TimedOut,
+        balls_cleared: 0,
+        shots_fired: 0,
+        combos: 0,
+        chains: 0,
+        moves: 0,
+        frames: 0,
+    };
+    let mut combo_streak = 0;
+    let budget = ThinkBudget::rollouts(level.rollouts);
+    let mut held_shot = None;
+
+    for frame in 0..level.max_frames {
+        if frame % level.shoot_every_frames == 0 && held_shot.is_none() {
+            held_shot = match bot::suggest_shot(&frog, &sim.state, strategy, budget) {
+                BotMove::Nothing => None,
+                BotMove::Shoot(point) => Some(point),
+                BotMove::SwapShoot(point) => {
+                    std::mem::swap(&mut frog.active_ball, &mut frog.next_ball);
+                    Some(point)
+                }
+                BotMove::MoveFrog(point) => {
+                    frog.destination = point;
+                    report.moves += 1;
+                    None
+                }
+            };
+        }
+
+        if let Some(projectile) = step_frog(&mut frog, &mut held_shot) {
+            sim.state.projectiles.push(projectile);
+            report.shots_fired += 1;
+            frog.active_ball = frog.next_ball;
+            frog.next_ball = deal_ball(&mut sim, &mut rng, &level.colors);
+        }
+
+        let tick = sim.tick();
+        report.frames = frame + 1;
+        report.balls_cleared += tick.cleared.len() as u32;
+        report.chains += tick.chains;
+        if tick.lost {
+            report.result = GameResult::Lost;
+            break;
+        }
+
+        // The balls in flight stay in the state so that the bots can see them
+        let mut projectiles = std::mem::take(&mut sim.state.projectiles);
+        let mut landed = vec![];
+        for (i, projectile) in projectiles.iter_mut().enumerate() {
+            let Some(impact) = projectile.step(&sim.state) else {
+                if projectile.is_off_screen() {
+                    landed.push(i);
+                    combo_streak = 0;
+                }
+                continue;
+            };
+
+            landed.push(i);
+            let cleared = sim.insert_ball_at(
+                impact.stream_idx,
+                impact.ball_idx,
+                impact.side,
+                projectile.ball,
+            );
+            if cleared.is_empty() {
+                combo_streak = 0;
+            } else {
+                report.balls_cleared += cleared.len() as u32;
+                combo_streak += 1;
+                if combo_streak > 1 {
+                    report.combos += 1;
+                }
+            }
+        }
+        for i in landed.into_iter().rev() {
+            projectiles.remove(i);
+        }
+        sim.state.projectiles = projectiles;
+
+        if sim.is_won() && sim.state.projectiles.is_empty() {
+            report.result = GameResult::Won;
+            break;
+        }
+    }
+
+    report
+}
+
+// Fire the shot the bot asked for if the frog stands still, then move the frog a frame
+// towards where it is heading. The bots aim a moving frog from there, so a shot asked for
+// on the way is held until it arrives
+fn step_frog(frog: &mut Frog, held_shot: &mut Option<Point>) -> Option<Projectile> {
+    let projectile = match *held_shot {
+        Some(target) if !frog.is_moving() => {
+            *held_shot = None;
+            Some(Projectile::new(frog, frog.active_ball, target))
+        }
+        _ => None,
+    };
+
+    let to_destination = frog.destination - frog.location;
+    let distance = to_destination.dot(&to_destination).sqrt();
+    frog.location = if distance <= FROG_SPEED {
+        frog.destination
+    } else {
+        frog.location + to_destination * (FROG_SPEED / distance)
+    };
+
+    projectile
+}
+
+// The frog is only given colors that are still on the board
+fn deal_ball(sim: &mut Simulator, rng: &mut Rng, level_colors: &[BallColor]) -> Ball {
+    let mut colors: Vec<_> = level_colors
+        .iter()
+        .copied()
+        .filter(|&color| sim.state.balls().any(|ball| ball.color == color))
+        .collect();
+    if colors.is_empty() {
+        colors = level_colors.to_vec();
+    }
+
+    let color = colors[rng.below(colors.len())];
+    sim.new_ball(color, BallEffect::None)
+}
+
+fn print_summary(level_name: &str, mode: StrategyInfo, reports: &[GameReport]) {
+    let games = reports.len().max(1) as f32;
+    let count = |result| reports.iter().filter(|r| r.result == result).count();
+    let mean = |f: fn(&GameReport) -> u32| reports.iter().map(|r| f(r) as f32).sum::<f32>() / games;
+
+    println!("{} on {}", mode, level_name);
+    println!(
+        "    games: {}, won: {}, lost: {}, timed out: {}",
+        reports.len(),
+        count(GameResult::Won),
+        count(GameResult::Lost),
+        count(GameResult::TimedOut)
+    );
+    println!(
+        "    per game: {:.1} balls cleared, {:.1} shots, {:.1} combos, {:.1} chains, {:.1} moves, {:.0} frames",
+        mean(|r| r.balls_cleared),
+        mean(|r| r.shots_fired),
+        mean(|r| r.combos),
+        mean(|r| r.chains),
+        mean(|r| r.moves),
+        mean(|r| r.frames),
+    );
+}
+
+// Write a curve made of straight segments between the points given on the command line
+pub fn make_curve(args: &[String]) -> Result<(), String> {
+    let (path, corners) = match args {
+        [path, corners @ ..] if corners.len() >= 2 => (path, corners),
+        _ => return Err(CURVE_USAGE.to_string()),
+    };
+    let corners = corners
+        .iter()
+        .map(|corner| parse_point("point", corner))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // The balls follow positions one unit apart
+    let mut positions = vec![corners[0]];
+    for segment in corners.windows(2) {
+        let (from, to) = (segment[0], segment[1]);
+        let steps = from.dist(&to).ceil() as usize;
+        positions.extend((1..=steps).map(|i| from + (to - from) * (i as f32 / steps as f32)));
+    }
+
+    let curve = curve_file::CurveFile::from_positions(&positions, &[])
+        .and_then(|curve| curve.validate().map(|_| curve))
+        .map_err(|err| format!("Could not make the curve: {}", err))?;
+    curve
+        .save(path)
+        .map_err(|err| format!("Could not write {}: {}", path, err))?;
+    println!("Wrote {} positions to {}", positions.len(), path);
+    Ok(())
+}
+
+fn parse_point(arg: &str, value: &str) -> Result<Point, String> {
+    let (x, y) = value
+        .split_once(',')
+        .ok_or_else(|| format!("Invalid value for {}: {}", arg, value))?;
+    Ok(Point {
+        x: parse(arg, x)?,
+        y: parse(arg, y)?,
+    })
+}
+
+fn parse_mode(value: &str) -> Result<StrategyInfo, String> {
+    StrategyInfo::find(value).ok_or_else(|| {
+        let names: Vec<_> = StrategyInfo::all().iter().map(|m| m.id).collect();
+        format!(
+            "Unknown mode {}, expected one of: all, {}",
+            value,
+            names.join(", ")
+        )
+    })
+}
+
+pub fn parse<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", arg, value))
+}
+
+#[test]
+fn games_are_reproducible() {
+    let points: Vec<_> = (0..1500)
+        .map(|x| Point {
+            x: 20. + (x % 600) as f32,
+            y: 40. + 50. * (x / 600) as f32,
+        })
+        .collect();
+    let level = LevelConfig {
+        name: "straight".into(),
+        curves: vec![Curve::from_points(points, vec![false; 1500])],
+        balls: 40,
+        initial_balls: 10,
+        colors: BallColor::ALL[..3].to_vec(),
+        forward_speed: 0.5,
+        frog_location: Point { x: 320., y: 400. },
+        frog_type: FrogType::Static(Point { x: 320., y: 400. }),
+        ball_exit_speed: 10.,
+        shoot_every_frames: 15,
+        max_frames: 5000,
+        rollouts: 10,
+    };
+
+    let color_bot = StrategyInfo::find("ColorBot").unwrap();
+    let report = play_game(&level, (color_bot.make)().as_mut(), 7);
+    assert_eq!(report, play_game(&level, (color_bot.make)().as_mut(), 7));
+    assert!(report.shots_fired > 0);
+}
+
+#[test]
+fn shots_wait_for_the_frog_to_arrive() {
+    use crate::libzuma::simulator::{loose_ball, straight_state};
+    use crate::libzuma::trajectory::trace_shot;
+    use BallColor::*;
+    let state = straight_state(&[Blue, Blue, Red, Red, Green, Green], 100., 0.);
+    let destination = Point { x: 228., y: 300. };
+    let mut frog = Frog {
+        location: Point { x: 500., y: 300. },
+        destination,
+        active_ball: loose_ball(10, Red),
+        next_ball: loose_ball(11, Red),
+        ball_exit_speed: 10.,
+    };
+
+    // The bot aims from where the frog is heading
+    let mut bot = (StrategyInfo::find("ColorBot").unwrap().make)();
+    let BotMove::Shoot(aim) =
+        bot::suggest_shot(&frog, &state, bot.as_mut(), ThinkBudget::rollouts(1))
+    else {
+        panic!("the bot should shoot");
+    };
+    let arrived = Frog {
+        location: destination,
+        ..frog
+    };
+    let aimed = trace_shot(&arrived, &state, aim).unwrap();
+    assert!(aimed.lands_next_to(0, 2) || aimed.lands_next_to(0, 3));
+    // From where the frog is now, the same shot goes elsewhere
+    assert_ne!(
+        Some(aimed),
+        Projectile::new(&frog, frog.active_ball, aim).first_impact(&state, f32::MAX)
+    );
+
+    let mut held_shot = Some(aim);
+    let mut frames = 0;
+    let projectile = loop {
+        if let Some(projectile) = step_frog(&mut frog, &mut held_shot) {
+            break projectile;
+        }
+        frames += 1;
+        assert!(
+            frames < 100,
+            "the shot should be fired once the frog arrives"
+        );
+    };
+    assert_eq!(destination, projectile.position);
+    assert_eq!(Some(aimed), projectile.first_impact(&state, f32::MAX));
+}
@@ -1,5 +1,52 @@
-use binrw::BinRead;
-use std::ops::{Add, Mul, MulAssign, Div, Neg, Sub};
+use binrw::{BinRead, BinWrite};
+use std::ops::{Add, Div, Mul, MulAssign, Neg, Range, Sub};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub mod curve_file;
+pub mod simulator;
+pub mod trajectory;
+
+use curve_file::{CurveError, CurveFile};
+
+// Duration of a game frame
+pub const FRAME_DURATION: Duration = Duration::from_millis(17);
+
+// Distance between the centers of two balls that touch each other
+pub const BALL_SPACING: f32 = 32.;
+// Balls further apart than this along the path have a gap between them
+pub const GAP_THRESHOLD: f32 = 32.5;
+// Balls closer than this to an exploding bomb ball are destroyed with it
+pub const BOMB_RADIUS: f32 = 80.;
+// Positions of the frog closer than this are the same position
+pub const FROG_POSITION_TOLERANCE: f32 = 2.;
+// The sizes and timings of the game the model of the chain relies on. The game's are the
+// default, the bots may be tuned with other ones and only apply them to their copy of the state
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Physics {
+    pub ball_spacing: f32, // Also how far an inserted ball pushes the balls in front of it
+    pub gap_threshold: f32, // Balls further apart than this along the path don't touch
+    pub shot_radius: f32,  // A shot hits the balls its center passes closer than this to
+    pub frame_duration: Duration,
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Self {
+            ball_spacing: BALL_SPACING,
+            gap_threshold: GAP_THRESHOLD,
+            shot_radius: BALL_SPACING,
+            frame_duration: FRAME_DURATION,
+        }
+    }
+}
+
+// More distinct positions than this on a single line make a slider rather than jumps
+const MAX_JUMP_POSITIONS: usize = 4;
+// Positions of a slider the bots consider moving to
+const SLIDER_STEPS: usize = 8;
+// Points on each side of a point over which the curve is measured to know how much it turns
+const CURVATURE_WINDOW: usize = 4;
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum BallColor {
@@ -11,6 +58,17 @@ pub enum BallColor {
     White,
 }
 
+impl BallColor {
+    pub const ALL: [Self; 6] = [
+        Self::Blue,
+        Self::Yellow,
+        Self::Red,
+        Self::Green,
+        Self::Purple,
+        Self::White,
+    ];
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum BallEffect {
     None,
@@ -20,7 +78,7 @@ pub enum BallEffect {
     Visor,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, BinRead)]
+#[derive(Clone, Copy, Debug, PartialEq, BinRead, BinWrite)]
 pub struct Point {
     pub x: f32,
     pub y: f32,
@@ -114,7 +172,7 @@ impl Point {
     }
 
     pub fn unit(&self) -> Point {
-        *self / self.dot(&self).sqrt()
+        *self / self.dot(self).sqrt()
     }
 }
 
@@ -128,16 +186,62 @@ pub struct Ball {
     pub id: u32,
 }
 
+impl Ball {
+    pub fn touches(&self, other: &Ball, gap_threshold: f32) -> bool {
+        (self.distance_along_path - other.distance_along_path).abs() <= gap_threshold
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct GameState {
+    pub streams: Vec<BallStream>,
+    // Balls shot by the frog that haven't landed yet
+    pub projectiles: Vec<trajectory::Projectile>,
+    pub time: Duration, // Time at which the state was observed
+    pub level: Option<Arc<Level>>,
+}
+
+impl GameState {
+    pub fn new() -> Self {
+        Self {
+            streams: vec![],
+            projectiles: vec![],
+            time: Duration::ZERO,
+            level: None,
+        }
+    }
+
+    // All the balls on the board, regardless of their stream
+    pub fn balls(&self) -> impl Iterator<Item = &Ball> {
+        self.streams.iter().flat_map(|stream| &stream.balls)
+    }
+
+    // Stream and index of the ball with the given id
+    pub fn find_ball(&self, id: u32) -> Option<(usize, usize)> {
+        self.streams
+            .iter()
+            .enumerate()
+            .find_map(|(stream_idx, stream)| {
+                let idx = stream.balls.iter().position(|ball| ball.id == id)?;
+                Some((stream_idx, idx))
+            })
+    }
+}
+
+// Balls following one curve, with everything that affects how they move
+#[derive(Clone, Debug)]
+pub struct BallStream {
     pub balls: Vec<Ball>,
     pub curve: Curve,
     pub forward_speed: f32,
     pub back_speed: f32,
     pub backwards_time_left: u32,
+    pub slowed_time_left: u32,
+    pub stopped_time_left: u32,
+    pub physics: Physics,
 }
 
-impl GameState {
+impl BallStream {
     pub fn new() -> Self {
         Self {
             balls: vec![],
@@ -145,162 +249,510 @@ impl GameState {
             forward_speed: 0.,
             back_speed: -1.,
             backwards_time_left: 0,
+            slowed_time_left: 0,
+            stopped_time_left: 0,
+            physics: Physics::default(),
         }
     }
 
-    fn clear_at(&mut self, index: usize) -> Option<()> {
+    // Split the balls into groups of touching balls, from start to end
+    pub fn segments(&self) -> Vec<Range<usize>> {
+        let mut segments = vec![];
+        let mut start = 0;
+        for i in 1..self.balls.len() {
+            if !self.balls[i - 1].touches(&self.balls[i], self.physics.gap_threshold) {
+                segments.push(start..i);
+                start = i;
+            }
+        }
+        if !self.balls.is_empty() {
+            segments.push(start..self.balls.len());
+        }
+
+        segments
+    }
+
+    // Side of the ball at `idx` on which a shot touching it at `hit` gets inserted
+    pub fn insert_side(&self, idx: usize, hit: Point) -> InsertSide {
+        if self.curve.points.len() < 2 {
+            return InsertSide::End;
+        }
+
+        // Where the hit is along the curve, looking no further than the balls next to it
+        // so that another loop of the curve passing close by is not picked
+        let ball = &self.balls[idx];
+        let spacing = self.physics.ball_spacing;
+        let around = ball.distance_along_path - spacing..ball.distance_along_path + spacing;
+        let Some((hit_dist, _)) = self.curve.nearest_dist(hit, around) else {
+            return InsertSide::End;
+        };
+        if hit_dist < ball.distance_along_path {
+            InsertSide::Start
+        } else {
+            InsertSide::End
+        }
+    }
+
+    // Clear the group of touching balls of the same color around `index`,
+    // returning the removed balls if there were at least 3 of them
+    pub fn clear_at(&mut self, index: usize) -> Option<Vec<Ball>> {
+        // If more than 3 balls are contiguous, actually clear them
+        let group = self.group_at(index);
+        if group.len() >= 3 {
+            Some(self.balls.drain(group).collect())
+        } else {
+            None
+        }
+    }
+
+    // The touching balls of the same color as the ball at `index`, including it
+    pub fn group_at(&self, index: usize) -> Range<usize> {
         let color_to_clear = self.balls[index].color;
 
+        let gap_threshold = self.physics.gap_threshold;
+
         // Determine the adjacent balls of the same color
         // Minimum index
         let mut min_clear = index;
-        while min_clear > 0 && self.balls[min_clear - 1].color == color_to_clear {
+        while min_clear > 0
+            && self.balls[min_clear - 1].color == color_to_clear
+            && self.balls[min_clear - 1].touches(&self.balls[min_clear], gap_threshold)
+        {
             min_clear -= 1;
         }
         // Maximum index
         let mut max_clear = index + 1;
-        while max_clear < self.balls.len() && self.balls[max_clear].color == color_to_clear {
+        while max_clear < self.balls.len()
+            && self.balls[max_clear].color == color_to_clear
+            && self.balls[max_clear - 1].touches(&self.balls[max_clear], gap_threshold)
+        {
             max_clear += 1;
         }
 
-        // If more than 3 balls are contiguous, actually clear them
-        if max_clear - min_clear >= 3 {
-            self.balls.drain(min_clear..max_clear);
+        min_clear..max_clear
+    }
+
+    // Clear the group around `index` like `clear_at`, along with the balls destroyed
+    // by the effects of the cleared balls. The effects that fired are added to `effects`
+    pub fn pop_group_at(
+        &mut self,
+        index: usize,
+        effects: &mut Vec<BallEffect>,
+    ) -> Option<Vec<Ball>> {
+        let mut popped = self.clear_at(index)?;
+
+        let bombs: Vec<_> = popped
+            .iter()
+            .filter(|ball| ball.effect == BallEffect::Bomb)
+            .map(|ball| ball.coordinates)
+            .collect();
+        if !bombs.is_empty() {
+            self.balls.retain(|ball| {
+                let destroyed = bombs
+                    .iter()
+                    .any(|bomb| bomb.dist_sq(&ball.coordinates) < BOMB_RADIUS.powi(2));
+                if destroyed {
+                    popped.push(*ball);
+                }
+                !destroyed
+            });
+        }
+
+        effects.extend(
+            popped
+                .iter()
+                .map(|ball| ball.effect)
+                .filter(|&effect| effect != BallEffect::None),
+        );
+        Some(popped)
+    }
+
+    // Clear the group around `index`, then keep clearing while the balls on both sides
+    // of the gap have the same color, as they roll back together and pop in turn
+    pub fn clear_cascade_at(&mut self, index: usize) -> Cascade {
+        let mut cascade = Cascade::default();
+
+        let mut index = index;
+        while let Some(popped) = self.pop_group_at(index, &mut cascade.effects) {
+            let gap_start = popped
+                .iter()
+                .map(|ball| ball.distance_along_path)
+                .fold(f32::MAX, f32::min);
+            cascade.steps.push(popped);
+
+            // First ball after the gap
+            let after = self
+                .balls
+                .partition_point(|ball| ball.distance_along_path < gap_start);
+            if after == 0
+                || after == self.balls.len()
+                || self.balls[after - 1].color != self.balls[after].color
+            {
+                break;
+            }
+
+            // The segment after the gap gets pulled back against the one before it
+            let segment_end = (after + 1..self.balls.len())
+                .find(|&i| !self.balls[i - 1].touches(&self.balls[i], self.physics.gap_threshold))
+                .unwrap_or(self.balls.len());
+            let pull = self.balls[after].distance_along_path
+                - self.balls[after - 1].distance_along_path
+                - self.physics.ball_spacing;
+            for ball in &mut self.balls[after..segment_end] {
+                ball.distance_along_path -= pull;
+            }
+            self.update_positions();
+
+            index = after;
         }
 
-        Some(())
+        cascade
     }
+
+    // Insert `ball` next to the ball at `target_idx`, pushing the balls in front of it
+    // to make room. Returns the index of the inserted ball
+    pub fn insert_ball_at(&mut self, target_idx: usize, side: InsertSide, ball: Ball) -> usize {
+        let target = self.balls[target_idx];
+        let (idx, distance_along_path) = match side {
+            InsertSide::Start => (target_idx, target.distance_along_path),
+            InsertSide::End => (
+                target_idx + 1,
+                target.distance_along_path + self.physics.ball_spacing,
+            ),
+        };
+
+        self.balls.insert(
+            idx,
+            Ball {
+                coordinates: target.coordinates,
+                is_reachable: target.is_reachable,
+                distance_along_path,
+                ..ball
+            },
+        );
+        self.push_forward_from(idx + 1);
+        self.update_positions();
+
+        idx
+    }
+
+    // What would happen if `ball` was inserted next to the ball at `target_idx`
+    pub fn predict_shot(&self, target_idx: usize, side: InsertSide, ball: Ball) -> Cascade {
+        let mut state = self.clone();
+        let idx = state.insert_ball_at(target_idx, side, ball);
+        state.clear_cascade_at(idx)
+    }
+
+    // Push balls forward starting at `from` so that none of them overlap
+    pub fn push_forward_from(&mut self, from: usize) {
+        for i in from.max(1)..self.balls.len() {
+            let min_dist = self.balls[i - 1].distance_along_path + self.physics.ball_spacing;
+            if self.balls[i].distance_along_path < min_dist {
+                self.balls[i].distance_along_path = min_dist;
+            }
+        }
+    }
+
+    // Recompute the coordinates of the balls from their distance along the curve
+    pub fn update_positions(&mut self) {
+        if self.curve.points.len() < 2 {
+            return;
+        }
+
+        for ball in &mut self.balls {
+            ball.coordinates = self.curve.get_pos_at_dist(ball.distance_along_path);
+            ball.is_reachable = !self.curve.get_tunnel_at_dist(ball.distance_along_path);
+        }
+    }
+}
+
+// Result of a chain reaction of clears
+#[derive(Clone, Debug, Default)]
+pub struct Cascade {
+    pub steps: Vec<Vec<Ball>>, // Balls popped at each step, the first one being the initial clear
+    pub effects: Vec<BallEffect>, // Effects of the popped balls, in the order they fired
 }
 
-#[derive(Debug)]
+impl Cascade {
+    // Number of times the chain continued after the initial clear
+    pub fn depth(&self) -> usize {
+        self.steps.len().saturating_sub(1)
+    }
+
+    pub fn popped(&self) -> usize {
+        self.steps.iter().map(Vec::len).sum()
+    }
+
+    #[cfg(test)]
+    pub fn popped_per_step(&self) -> Vec<usize> {
+        self.steps.iter().map(Vec::len).collect()
+    }
+}
+
+// Side of the target ball on which a shot ball gets inserted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InsertSide {
+    Start, // Between the target and the ball before it
+    End,   // Between the target and the ball after it
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum FrogType {
     Static(Point),        // If the frog does not move
     Jumper(Vec<Point>),   // If the frog has multiple possible positions
     Slider(Point, Point), // If the frog can move along a slider
 }
 
+impl FrogType {
+    // Guess how the frog moves from the positions it was seen at
+    pub fn from_positions(seen: &[Point]) -> Self {
+        let mut positions: Vec<Point> = vec![];
+        for &position in seen {
+            if !positions
+                .iter()
+                .any(|p| p.dist(&position) <= FROG_POSITION_TOLERANCE)
+            {
+                positions.push(position);
+            }
+        }
+
+        match positions[..] {
+            [] => FrogType::Static(Point { x: 0., y: 0. }),
+            [position] => FrogType::Static(position),
+            _ if positions.len() <= MAX_JUMP_POSITIONS => FrogType::Jumper(positions),
+            _ => {
+                // The two positions furthest apart are the ends of the slider,
+                // if every other position is between them
+                let (start, end) = positions
+                    .iter()
+                    .flat_map(|a| positions.iter().map(move |b| (*a, *b)))
+                    .max_by(|(a, b), (c, d)| a.dist_sq(b).total_cmp(&c.dist_sq(d)))
+                    .unwrap();
+                let on_line = positions.iter().all(|p| {
+                    (p.dist(&start) + p.dist(&end) - start.dist(&end)).abs()
+                        <= FROG_POSITION_TOLERANCE
+                });
+                if on_line {
+                    FrogType::Slider(start, end)
+                } else {
+                    FrogType::Jumper(positions)
+                }
+            }
+        }
+    }
+
+    // Where the frog can stand, with a few evenly spaced stops along a slider
+    pub fn positions(&self) -> Vec<Point> {
+        match self {
+            FrogType::Static(position) => vec![*position],
+            FrogType::Jumper(positions) => positions.clone(),
+            FrogType::Slider(start, end) => (0..=SLIDER_STEPS)
+                .map(|i| *start + (*end - *start) * (i as f32 / SLIDER_STEPS as f32))
+                .collect(),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct Frog {
-    pub location: Point,
+    pub location: Point, // Where the frog is right now, which is where the balls leave from
+    pub destination: Point, // Where it is heading, the same as `location` when it stands still
     pub active_ball: Ball,
     pub next_ball: Ball,
     pub ball_exit_speed: f32,
 }
 
-// Represents a curve that the balls follow along
+impl Frog {
+    pub fn is_moving(&self) -> bool {
+        self.location.dist(&self.destination) > FROG_POSITION_TOLERANCE
+    }
+}
+
+// The level being played, as the game describes it
+#[derive(Clone, Debug)]
+pub struct Level {
+    pub id: String,           // Name of the level in the game files, e.g. "spiral"
+    pub display_name: String, // As shown to the player
+    pub frog_position: Point,
+    pub frog_type: FrogType,
+    pub background: String, // Image path, relative to the game directory
+    pub curves: Vec<Curve>, // The curve at index i is followed by the stream at index i
+}
+
+// Represents a curve that the balls follow along.
+// The points are shared between clones, so that copies of the game state stay cheap
 #[derive(Clone, Debug)]
 pub struct Curve {
     last_loaded: String,
-    pub points: Vec<Point>,
-    is_tunnel: Vec<bool>,
+    pub points: Arc<Vec<Point>>,
+    is_tunnel: Arc<Vec<bool>>,
 }
 
 impl Curve {
     pub fn new() -> Self {
         Self {
             last_loaded: "".into(),
-            points: vec![],
-            is_tunnel: vec![],
+            points: Arc::new(vec![]),
+            is_tunnel: Arc::new(vec![]),
         }
     }
 
-    // Read curve data from given file path
-    pub fn read_from_file(&mut self, path: String) -> Option<()> {
-        use std::fs;
+    pub fn from_points(points: Vec<Point>, is_tunnel: Vec<bool>) -> Self {
+        Self {
+            last_loaded: "".into(),
+            points: Arc::new(points),
+            is_tunnel: Arc::new(is_tunnel),
+        }
+    }
+
+    // Total length of the path, in the same unit as `Ball::distance_along_path`
+    pub fn length(&self) -> f32 {
+        self.points.len() as f32
+    }
 
+    // Read curve data from given file path
+    pub fn read_from_file(&mut self, path: String) -> Result<(), CurveError> {
         if self.last_loaded == path {
-            return Some(());
+            return Ok(());
         }
 
-        let mut file = fs::File::open(path.clone()).ok()?;
-        let curve = BinCurve::read_ne(&mut file).ok()?;
-
+        let file = CurveFile::load(&path)?;
+        *self = Self::from_file(&file)?;
         self.last_loaded = path;
-        let mut current_point = curve.start_point.0;
-        self.points = curve
-            .deltas
-            .iter()
-            .map(|p| {
-                current_point.x += (p.x as f32) / 100.;
-                current_point.y += (p.y as f32) / 100.;
-                current_point
-            })
-            .collect();
+        Ok(())
+    }
 
-        self.is_tunnel = curve
-            .deltas
-            .iter()
-            .map(|p| p.tunnel_data.is_tunnel != 0)
-            .collect();
+    // Path of the file the curve was last read from, empty when it wasn't read from one
+    pub fn path(&self) -> &str {
+        &self.last_loaded
+    }
+
+    // A curve the balls can follow needs at least two points
+    pub fn from_file(file: &CurveFile) -> Result<Self, CurveError> {
+        let positions = file.positions();
+        if positions.len() < 2 {
+            return Err(CurveError::TooShort);
+        }
+        Ok(Self::from_points(
+            positions,
+            file.infos().iter().map(|info| info.tunnel != 0).collect(),
+        ))
+    }
+
+    // The curve as a file the game could load
+    pub fn to_file(&self) -> Result<CurveFile, CurveError> {
+        CurveFile::from_positions(&self.points, &self.is_tunnel)
+    }
+
+    // The queries below take distances the way the game counts them, one unit per point,
+    // and interpolate between the points. Curves with less than two points, such as the empty
+    // one of a stream whose curve couldn't be read, have no segment: their positions are at
+    // their only point or at the origin, and the other queries give neutral answers
+
+    fn is_too_short(&self) -> bool {
+        self.points.len() < 2
+    }
 
-        Some(())
+    // Segment the distance falls in, and how far along it from 0 to 1
+    fn segment_at(&self, dist: f32) -> (usize, f32) {
+        let last = self.points.len().saturating_sub(2);
+        let dist = dist.clamp(0., (last + 1) as f32);
+        let idx = (dist as usize).min(last);
+        (idx, dist - idx as f32)
     }
 
     // Return the position at the given distance from the start
     pub fn get_pos_at_dist(&self, dist: f32) -> Point {
-        // This trick works only because the points are exactly 1 unit apart
-        let idx = (dist as usize).min(self.points.len() - 1);
-        self.points[idx]
+        if self.is_too_short() {
+            return self
+                .points
+                .first()
+                .copied()
+                .unwrap_or(Point { x: 0., y: 0. });
+        }
+        let (idx, t) = self.segment_at(dist);
+        self.points[idx] + (self.points[idx + 1] - self.points[idx]) * t
     }
 
     pub fn get_tunnel_at_dist(&self, dist: f32) -> bool {
-        let idx = (dist as usize).min(self.points.len() - 1);
-        self.is_tunnel[idx]
+        let idx = (dist.max(0.) as usize).min(self.is_tunnel.len().saturating_sub(1));
+        self.is_tunnel.get(idx).copied().unwrap_or(false)
+    }
+
+    // Direction of the curve at a point, from its neighbours so that it turns smoothly
+    fn tangent_at_point(&self, idx: usize) -> Point {
+        let before = idx.saturating_sub(1);
+        let after = (idx + 1).min(self.points.len() - 1);
+        self.points[after] - self.points[before]
     }
 
+    // Direction of travel of the balls at the given distance, as a unit vector
+    pub fn get_tangent_at_dist(&self, dist: f32) -> Point {
+        if self.is_too_short() {
+            return Point { x: 1., y: 0. };
+        }
+        let (idx, t) = self.segment_at(dist);
+        let tangent = self.tangent_at_point(idx) * (1. - t) + self.tangent_at_point(idx + 1) * t;
+        tangent.unit()
+    }
+
+    // The tangent rotated by 90 degrees, as a unit vector
     pub fn get_normal_at_dist(&self, dist: f32) -> Point {
-        let idx = (dist as usize).min(self.points.len() - 2);
-        // The delta between consecutive points rotated by 90 degrees
-        // is a pretty good normal
+        let tangent = self.get_tangent_at_dist(dist);
         Point {
-            x: self.points[idx + 1].y - self.points[idx].y,
-            y: self.points[idx].x - self.points[idx + 1].x,
+            x: tangent.y,
+            y: -tangent.x,
         }
     }
-}
-
-#[allow(dead_code)]
-#[derive(BinRead)]
-struct BinCurveTunnelData {
-    is_tunnel: u8,
-    unk2: u8, // Possibly a z thing to define tunnels
-}
 
-#[allow(dead_code)]
-#[derive(BinRead)]
-struct BinCurvePoint {
-    x: u32,
-    y: u32,
-    tunnel_data: BinCurveTunnelData,
-}
-
-#[allow(dead_code)]
-#[derive(BinRead)]
-struct BinCurveDelta {
-    x: i8,
-    y: i8,
-    tunnel_data: BinCurveTunnelData,
-}
+    // How sharply the curve turns at a point, as the inverse of the radius of the turn.
+    // The points are only stored to a hundredth of a unit, so the turn is measured
+    // over a few of them
+    fn curvature_at_point(&self, idx: usize) -> f32 {
+        let before = self.points[idx.saturating_sub(CURVATURE_WINDOW)];
+        let after = self.points[(idx + CURVATURE_WINDOW).min(self.points.len() - 1)];
+        let (incoming, outgoing) = (self.points[idx] - before, after - self.points[idx]);
+        let length = (incoming.dot(&incoming).sqrt() + outgoing.dot(&outgoing).sqrt()) / 2.;
+        if length == 0. {
+            return 0.;
+        }
 
-#[allow(dead_code)]
-#[derive(BinRead)]
-#[br(magic = b"CURV")]
-struct BinCurve {
-    // Header
-    unkown1: i32,
-    unkown2: i32,
-    size: u32,
+        let cross = incoming.x * outgoing.y - incoming.y * outgoing.x;
+        // Positive when the curve turns towards its normal
+        -cross.atan2(incoming.dot(&outgoing)) / length
+    }
 
-    // Points section
-    _point_count: u32,
-    #[br(count = _point_count)]
-    points: Vec<BinCurvePoint>,
+    pub fn get_curvature_at_dist(&self, dist: f32) -> f32 {
+        if self.is_too_short() {
+            return 0.;
+        }
+        let (idx, t) = self.segment_at(dist);
+        self.curvature_at_point(idx) * (1. - t) + self.curvature_at_point(idx + 1) * t
+    }
 
-    // Deltas section
-    _deltas_count: u32,
-    start_point: (Point, BinCurveTunnelData),
-    #[br(count = _deltas_count-1)]
-    deltas: Vec<BinCurveDelta>,
+    // Distance along the curve of the point of the curve closest to `point`, among the
+    // distances in `range`, and how far that point is from `point`
+    pub fn nearest_dist(&self, point: Point, range: Range<f32>) -> Option<(f32, f32)> {
+        if self.is_too_short() {
+            return None;
+        }
+        let (first, _) = self.segment_at(range.start);
+        let (last, _) = self.segment_at(range.end);
+        (first..=last)
+            .map(|idx| {
+                let (start, end) = (self.points[idx], self.points[idx + 1]);
+                let along = end - start;
+                let len_sq = along.dot(&along);
+                let t = if len_sq > 0. {
+                    ((point - start).dot(&along) / len_sq).clamp(0., 1.)
+                } else {
+                    0.
+                };
+                let dist = (idx as f32 + t).clamp(range.start, range.end);
+                (dist, point.dist(&self.get_pos_at_dist(dist)))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
 }
 
 #[test]
@@ -324,21 +776,98 @@ fn clear() {
         id: 0,
     };
 
-    let seq = GameState {
-        balls: vec![
-            blue_ball.clone(),
-            red_ball.clone(),
-            red_ball.clone(),
-            red_ball.clone(),
-            blue_ball.clone(),
-        ],
-        ..GameState::new()
+    let seq = BallStream {
+        balls: vec![blue_ball, red_ball, red_ball, red_ball, blue_ball],
+        ..BallStream::new()
     };
 
     let mut seq_cleared = seq.clone();
     seq_cleared.clear_at(3);
 
-    // We test that the balls have been cleared
+    // We test that the balls have been cleared: the three reds go, the two blues stay.
+    // This used to expect a single ball left, which clearing three of five never gave
     assert_eq!(5, seq.balls.len());
-    assert_eq!(1, seq_cleared.balls.len());
+    assert_eq!(2, seq_cleared.balls.len());
+}
+
+#[test]
+fn frog_type_from_positions() {
+    let p = |x: f32, y: f32| Point { x, y };
+
+    let still = [p(320., 240.), p(321., 240.), p(320., 240.)];
+    assert_eq!(
+        FrogType::Static(p(320., 240.)),
+        FrogType::from_positions(&still)
+    );
+
+    let pads = [p(100., 100.), p(500., 100.), p(100., 100.), p(300., 400.)];
+    assert_eq!(
+        FrogType::Jumper(vec![p(100., 100.), p(500., 100.), p(300., 400.)]),
+        FrogType::from_positions(&pads)
+    );
+
+    let sliding: Vec<_> = (0..10).map(|i| p(200. + 20. * i as f32, 440.)).collect();
+    let slider = FrogType::from_positions(&sliding);
+    assert!(
+        slider == FrogType::Slider(p(200., 440.), p(380., 440.))
+            || slider == FrogType::Slider(p(380., 440.), p(200., 440.))
+    );
+    assert_eq!(SLIDER_STEPS + 1, slider.positions().len());
+}
+
+#[test]
+fn curve_queries_interpolate() {
+    // A circle of radius 50 around (320, 240), with points one unit apart
+    let radius = 50.;
+    let points: Vec<_> = (0..300)
+        .map(|i| {
+            let angle = i as f32 / radius;
+            Point {
+                x: 320. + radius * angle.cos(),
+                y: 240. + radius * angle.sin(),
+            }
+        })
+        .collect();
+    let curve = Curve::from_points(points.clone(), vec![false; 300]);
+
+    let halfway = (points[10] + points[11]) * 0.5;
+    assert!(curve.get_pos_at_dist(10.5).dist(&halfway) < 1e-3);
+    assert_eq!(points[299], curve.get_pos_at_dist(1000.));
+
+    let tangent = curve.get_tangent_at_dist(10.5);
+    let expected = Point {
+        x: -(10.5 / radius).sin(),
+        y: (10.5 / radius).cos(),
+    };
+    assert!(tangent.dist(&expected) < 1e-3);
+    // The normal points out of the circle, and the curve turns away from it
+    let normal = curve.get_normal_at_dist(10.5);
+    assert!(normal.dot(&(halfway - Point { x: 320., y: 240. })) > 0.);
+    assert!((curve.get_curvature_at_dist(100.3) + 1. / radius).abs() < 1e-3);
+
+    // A point off the curve maps back to the distance it is closest to
+    let outside = curve.get_pos_at_dist(42.25) + curve.get_normal_at_dist(42.25) * 5.;
+    let (dist, away) = curve.nearest_dist(outside, 0.0..curve.length()).unwrap();
+    assert!((dist - 42.25).abs() < 0.05);
+    assert!((away - 5.).abs() < 0.05);
+    let (dist, _) = curve.nearest_dist(outside, 100.0..200.).unwrap();
+    assert_eq!(100., dist);
+
+    // Curves without a segment answer without reading past their points
+    for points in [vec![], vec![points[0]]] {
+        let short = Curve::from_points(points.clone(), vec![true; points.len()]);
+        let origin = points.first().copied().unwrap_or(Point { x: 0., y: 0. });
+        assert_eq!(origin, short.get_pos_at_dist(10.));
+        assert_eq!(!points.is_empty(), short.get_tunnel_at_dist(10.));
+        assert_eq!(0., short.get_curvature_at_dist(10.));
+        assert_eq!(None, short.nearest_dist(outside, 0.0..10.));
+    }
+    let single = CurveFile::from_positions(&points[..2], &[]).map(|mut file| {
+        file.deltas.clear();
+        file
+    });
+    assert_eq!(
+        Err(CurveError::TooShort),
+        Curve::from_file(&single.unwrap()).map(|_| ())
+    );
 }
@@ -0,0 +1,327 @@
+use super::*;
+use binrw::{binrw, BinResult};
+use std::io::{Cursor, Read, Seek, Write};
+use std::path::Path;
+
+// The curve files of the game, found next to each level as <curve>.dat.
+// They hold the path twice: once as absolute points rounded to whole units,
+// and once as a start point followed by small steps. The balls follow the positions the steps
+// lead to, the start point itself isn't one of them.
+// The header likely comes from the level editor and isn't needed to play, what its
+// fields mean is unknown. Everything is kept as read so that a file is written back unchanged.
+// Files are loaded as they are, `validate` tells whether both sections agree
+
+// The steps are stored in hundredths of a unit
+const DELTA_SCALE: f32 = 100.;
+// How far apart the two sections may put a point, since the absolute points are rounded
+const POINT_TOLERANCE: f32 = 1.5;
+
+#[binrw]
+#[brw(little, magic = b"CURV")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CurveFile {
+    // Meaning unknown, maybe the editor settings the curve was drawn with
+    pub unknown_0: i32,
+    pub unknown_1: i32,
+    pub unknown_2: u32,
+
+    // Points section
+    #[br(temp)]
+    #[bw(calc = points.len() as u32)]
+    point_count: u32,
+    #[br(count = point_count)]
+    pub points: Vec<CurvePoint>,
+
+    // Deltas section, its count includes the start point
+    #[br(temp)]
+    #[br(assert(path_length > 0, "curve has no start point"))]
+    #[bw(calc = deltas.len() as u32 + 1)]
+    path_length: u32,
+    pub start: Point,
+    pub start_info: PointInfo,
+    #[br(count = path_length - 1)]
+    pub deltas: Vec<CurveDelta>,
+}
+
+#[binrw]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PointInfo {
+    pub tunnel: u8, // Not 0 where the balls go under a tunnel and can't be hit
+    // Meaning unknown, maybe which part is drawn on top where the curve crosses itself
+    pub unknown: u8,
+}
+
+#[binrw]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CurvePoint {
+    pub x: u32,
+    pub y: u32,
+    pub info: PointInfo,
+}
+
+#[binrw]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CurveDelta {
+    pub x: i8,
+    pub y: i8,
+    pub info: PointInfo,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CurveError {
+    Unreadable(String),       // Not a curve file, or it couldn't be read or written
+    TooShort,                 // A curve needs at least two positions
+    StepTooLong(usize),       // Index of a position too far from the previous one for a delta
+    PointCount(usize, usize), // Absolute points, and positions given by the deltas
+    Mismatch(usize, Point, Point), // Index, absolute point, and position given by the deltas
+}
+
+impl std::fmt::Display for CurveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CurveError::Unreadable(err) => write!(f, "Invalid curve file: {}", err),
+            CurveError::TooShort => write!(f, "The curve has less than two points"),
+            CurveError::StepTooLong(idx) => {
+                write!(f, "Point {} is too far from the previous one", idx)
+            }
+            CurveError::PointCount(points, positions) => write!(
+                f,
+                "The curve has {} points but {} positions from its deltas",
+                points, positions
+            ),
+            CurveError::Mismatch(idx, point, position) => write!(
+                f,
+                "Point {} is at {:?} but at {:?} from the deltas",
+                idx, point, position
+            ),
+        }
+    }
+}
+
+impl CurveFile {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CurveError> {
+        let data = std::fs::read(path).map_err(|err| CurveError::Unreadable(err.to_string()))?;
+        let curve = Self::read_from(&mut Cursor::new(data))
+            .map_err(|err| CurveError::Unreadable(err.to_string()))?;
+        Ok(curve)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CurveError> {
+        let mut data = Cursor::new(vec![]);
+        self.write_to(&mut data)
+            .map_err(|err| CurveError::Unreadable(err.to_string()))?;
+        std::fs::write(path, data.into_inner())
+            .map_err(|err| CurveError::Unreadable(err.to_string()))
+    }
+
+    pub fn read_from<R: Read + Seek>(reader: &mut R) -> BinResult<Self> {
+        Self::read(reader)
+    }
+
+    pub fn write_to<W: Write + Seek>(&self, writer: &mut W) -> BinResult<()> {
+        self.write(writer)
+    }
+
+    // Build the file of a curve going through the given positions, each of them
+    // at most a little over one unit from the previous one. The start point is the first
+    // position, which the first step stays on.
+    // The unknown fields are written as 0, whether the game accepts that hasn't been checked
+    pub fn from_positions(positions: &[Point], is_tunnel: &[bool]) -> Result<Self, CurveError> {
+        if positions.len() < 2 {
+            return Err(CurveError::TooShort);
+        }
+        let start = positions[0];
+        let info = |idx: usize| PointInfo {
+            tunnel: is_tunnel.get(idx).copied().unwrap_or_default() as u8,
+            unknown: 0,
+        };
+
+        // The steps are taken between rounded positions so that their rounding doesn't add up
+        let hundredths = |p: Point| {
+            (
+                ((p.x - start.x) * DELTA_SCALE).round() as i32,
+                ((p.y - start.y) * DELTA_SCALE).round() as i32,
+            )
+        };
+        let mut deltas = vec![];
+        let mut previous = (0, 0);
+        for (idx, &position) in positions.iter().enumerate() {
+            let current = hundredths(position);
+            let step = (
+                i8::try_from(current.0 - previous.0),
+                i8::try_from(current.1 - previous.1),
+            );
+            let (Ok(x), Ok(y)) = step else {
+                return Err(CurveError::StepTooLong(idx));
+            };
+            deltas.push(CurveDelta {
+                x,
+                y,
+                info: info(idx),
+            });
+            previous = current;
+        }
+
+        let mut file = Self {
+            unknown_0: 0,
+            unknown_1: 0,
+            unknown_2: 0,
+            points: vec![],
+            start,
+            start_info: info(0),
+            deltas,
+        };
+        file.points = file
+            .path()
+            .iter()
+            .zip(std::iter::once(info(0)).chain(file.infos()))
+            .map(|(p, info)| CurvePoint {
+                x: p.x.round().max(0.) as u32,
+                y: p.y.round().max(0.) as u32,
+                info,
+            })
+            .collect();
+
+        Ok(file)
+    }
+
+    // Positions along the path, one unit apart, as the game follows them:
+    // where each step leads, without the start point
+    pub fn positions(&self) -> Vec<Point> {
+        let mut sum = (0, 0);
+        self.deltas
+            .iter()
+            .map(|delta| {
+                sum.0 += delta.x as i32;
+                sum.1 += delta.y as i32;
+                Point {
+                    x: self.start.x + sum.0 as f32 / DELTA_SCALE,
+                    y: self.start.y + sum.1 as f32 / DELTA_SCALE,
+                }
+            })
+            .collect()
+    }
+
+    // Tunnel data of each position
+    pub fn infos(&self) -> Vec<PointInfo> {
+        self.deltas.iter().map(|delta| delta.info).collect()
+    }
+
+    // The start point followed by the positions, as many as the steps section counts
+    fn path(&self) -> Vec<Point> {
+        std::iter::once(self.start)
+            .chain(self.positions())
+            .collect()
+    }
+
+    // Check that both sections describe the same path. Files without absolute points are fine
+    pub fn validate(&self) -> Result<(), CurveError> {
+        if self.deltas.is_empty() {
+            return Err(CurveError::TooShort);
+        }
+        if self.points.is_empty() {
+            return Ok(());
+        }
+
+        let positions = self.path();
+        if self.points.len() != positions.len() {
+            return Err(CurveError::PointCount(self.points.len(), positions.len()));
+        }
+        for (idx, (point, position)) in self.points.iter().zip(positions).enumerate() {
+            let point = Point {
+                x: point.x as f32,
+                y: point.y as f32,
+            };
+            if point.dist(&position) > POINT_TOLERANCE {
+                return Err(CurveError::Mismatch(idx, point, position));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn test_positions() -> Vec<Point> {
+    (0..300)
+        .map(|i| {
+            let angle = i as f32 / 150.;
+            Point {
+                x: 320. + (100. + i as f32 / 10.) * angle.cos(),
+                y: 240. + (100. + i as f32 / 10.) * angle.sin(),
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn curve_file_round_trip() {
+    let positions = test_positions();
+    let tunnels: Vec<_> = (0..positions.len()).map(|i| i > 250).collect();
+    let curve = CurveFile::from_positions(&positions, &tunnels).unwrap();
+    assert_eq!(positions.len(), curve.positions().len());
+    assert_eq!(positions.len() + 1, curve.points.len());
+    for (expected, position) in positions.iter().zip(curve.positions()) {
+        assert!(expected.dist(&position) < 0.01);
+    }
+    assert_eq!(1, curve.infos()[260].tunnel);
+
+    // Fields the bot doesn't use are written back as they were read
+    let mut bytes = Cursor::new(vec![]);
+    let curve = CurveFile {
+        unknown_0: -3,
+        unknown_1: 1,
+        unknown_2: 42,
+        ..curve
+    };
+    curve.write_to(&mut bytes).unwrap();
+    let bytes = bytes.into_inner();
+    assert_eq!(b"CURV", &bytes[..4]);
+    let read = CurveFile::read_from(&mut Cursor::new(&bytes)).unwrap();
+    assert_eq!(curve, read);
+    let mut written = Cursor::new(vec![]);
+    read.write_to(&mut written).unwrap();
+    assert_eq!(bytes, written.into_inner());
+}
+
+#[test]
+fn curve_file_validation() {
+    let positions = test_positions();
+    let mut curve = CurveFile::from_positions(&positions, &[]).unwrap();
+    assert_eq!(Ok(()), curve.validate());
+
+    curve.points[100].x += 5;
+    assert!(matches!(
+        curve.validate(),
+        Err(CurveError::Mismatch(100, _, _))
+    ));
+    curve.points.pop();
+    assert_eq!(Err(CurveError::PointCount(300, 301)), curve.validate());
+    curve.points.clear();
+    assert_eq!(Ok(()), curve.validate());
+
+    // Files that don't agree with themselves still load
+    let path = std::env::temp_dir().join(format!("curve-validation-{}.dat", std::process::id()));
+    curve.points = CurveFile::from_positions(&positions[..10], &[])
+        .unwrap()
+        .points;
+    assert_eq!(Err(CurveError::PointCount(11, 301)), curve.validate());
+    curve.save(&path).unwrap();
+    let loaded = CurveFile::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(Ok(curve), loaded);
+
+    let far = [positions[0], positions[0] + Point { x: 2., y: 0. }];
+    assert_eq!(
+        Err(CurveError::StepTooLong(1)),
+        CurveFile::from_positions(&far, &[]).map(|_| ())
+    );
+    assert_eq!(
+        Err(CurveError::TooShort),
+        CurveFile::from_positions(&[], &[]).map(|_| ())
+    );
+    assert_eq!(
+        Err(CurveError::TooShort),
+        CurveFile::from_positions(&positions[..1], &[]).map(|_| ())
+    );
+}
@@ -0,0 +1,439 @@
+use super::*;
+
+// Speed at which a segment rolls back towards a segment ending with the same color
+const DEFAULT_PULL_SPEED: f32 = 3.;
+// Roughly how long popping a reverse or slow ball lasts
+const REVERSE_FRAMES: u32 = 180;
+const SLOW_FRAMES: u32 = 360;
+// Factor applied to the forward speed while the chain is slowed
+pub const SLOW_FACTOR: f32 = 0.5;
+
+#[derive(Clone, Debug, Default)]
+pub struct TickOutcome {
+    pub cleared: Vec<Ball>,
+    pub chains: u32, // Number of groups that cleared because two segments rolled together
+    pub lost: bool,
+}
+
+// Small xorshift generator, so that simulated games can be reproduced from a seed
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+    }
+
+    // A generator for one use of a seed, e.g. dealing the frog's balls or feeding the stream
+    // at `index`. Its numbers are unrelated to those of other uses and of neighbouring seeds
+    pub fn derive(seed: u64, purpose: &str, index: u64) -> Self {
+        let purpose = purpose.bytes().fold(0xCBF29CE484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001B3)
+        });
+        Self::new(mix(mix(mix(seed) ^ purpose) ^ index))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // Uniform number in 0..n
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // Uniform number in 0..1
+    pub fn unit(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+// Scramble the bits of a number, so that close inputs give unrelated outputs (splitmix64)
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E3779B97F4A7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+    x ^ (x >> 31)
+}
+
+// Feeds new balls at the start of the curve, like the hole at the start of a level
+#[derive(Clone, Debug)]
+pub struct Spawner {
+    pub remaining: u32,
+    pub colors: Vec<BallColor>,
+    pub rng: Rng,
+}
+
+impl Spawner {
+    fn next_color(&mut self) -> BallColor {
+        self.colors[self.rng.below(self.colors.len())]
+    }
+}
+
+// Deterministic model of the ball streams, advanced one game frame at a time
+#[derive(Clone, Debug)]
+pub struct Simulator {
+    pub state: GameState,
+    pub frame: u32,
+    pub pull_speed: f32,
+    pub spawners: Vec<Spawner>, // The spawner at index i feeds the stream at index i
+    next_ball_id: u32,
+}
+
+impl Simulator {
+    pub fn new(state: GameState) -> Self {
+        let next_ball_id = state.balls().map(|ball| ball.id + 1).max().unwrap_or(0);
+
+        let mut sim = Self {
+            state,
+            frame: 0,
+            pull_speed: DEFAULT_PULL_SPEED,
+            spawners: vec![],
+            next_ball_id,
+        };
+        for stream in &mut sim.state.streams {
+            stream.update_positions();
+        }
+        sim
+    }
+
+    // Run the simulation for the given amount of frames, stopping early if the game is lost
+    #[cfg(test)]
+    pub fn advance(&mut self, frames: u32) -> TickOutcome {
+        let mut outcome = TickOutcome::default();
+        for _ in 0..frames {
+            let tick = self.tick();
+            outcome.cleared.extend(tick.cleared);
+            outcome.chains += tick.chains;
+            if tick.lost {
+                outcome.lost = true;
+                break;
+            }
+        }
+
+        outcome
+    }
+
+    // Advance the simulation by one frame
+    pub fn tick(&mut self) -> TickOutcome {
+        let mut outcome = TickOutcome::default();
+        self.frame += 1;
+        self.state.time += FRAME_DURATION;
+
+        for stream_idx in 0..self.state.streams.len() {
+            self.spawn(stream_idx);
+            let tick = self.tick_stream(stream_idx);
+            outcome.cleared.extend(tick.cleared);
+            outcome.chains += tick.chains;
+            outcome.lost |= tick.lost;
+        }
+
+        outcome
+    }
+
+    fn tick_stream(&mut self, stream_idx: usize) -> TickOutcome {
+        let mut outcome = TickOutcome::default();
+        let stream = &mut self.state.streams[stream_idx];
+        if stream.balls.is_empty() {
+            return outcome;
+        }
+
+        let reversing = stream.backwards_time_left > 0;
+        let forward_speed = if stream.stopped_time_left > 0 {
+            0.
+        } else if stream.slowed_time_left > 0 {
+            stream.forward_speed * SLOW_FACTOR
+        } else {
+            stream.forward_speed
+        };
+        stream.backwards_time_left = stream.backwards_time_left.saturating_sub(1);
+        stream.slowed_time_left = stream.slowed_time_left.saturating_sub(1);
+        stream.stopped_time_left = stream.stopped_time_left.saturating_sub(1);
+
+        // Move every segment according to what is pushing or pulling it
+        let segments = stream.segments();
+        let balls = &mut stream.balls;
+        for (i, segment) in segments.iter().enumerate() {
+            let pulled_back =
+                i > 0 && balls[segment.start].color == balls[segments[i - 1].end - 1].color;
+
+            let speed = if pulled_back {
+                -self.pull_speed
+            } else if reversing {
+                if i == segments.len() - 1 {
+                    stream.back_speed
+                } else {
+                    0.
+                }
+            } else if i == 0 {
+                forward_speed
+            } else {
+                0.
+            };
+
+            for ball in &mut balls[segment.clone()] {
+                ball.distance_along_path += speed;
+            }
+        }
+
+        // Resolve overlaps: when reversing, the last segment pushes the others back,
+        // otherwise segments behind push the ones in front
+        let physics = stream.physics;
+        if reversing {
+            let mut i = balls.len() - 1;
+            while i > 0
+                && balls[i].distance_along_path - balls[i - 1].distance_along_path
+                    < physics.ball_spacing
+            {
+                balls[i - 1].distance_along_path =
+                    balls[i].distance_along_path - physics.ball_spacing;
+                i -= 1;
+            }
+        }
+        stream.push_forward_from(1);
+        let balls = &stream.balls;
+
+        // Segments that came into contact with a ball of the same color may clear
+        let junctions: Vec<_> = segments
+            .iter()
+            .skip(1)
+            .map(|segment| segment.start)
+            .filter(|&idx| balls[idx - 1].touches(&balls[idx], physics.gap_threshold))
+            .collect();
+        for &idx in junctions.iter().rev() {
+            // A clear at a later junction may have taken the balls of this one with it
+            let balls = &self.state.streams[stream_idx].balls;
+            if idx >= balls.len() {
+                continue;
+            }
+            if balls[idx - 1].color == balls[idx].color {
+                if let Some(cleared) = self.pop_group_at(stream_idx, idx) {
+                    outcome.cleared.extend(cleared);
+                    outcome.chains += 1;
+                }
+            }
+        }
+
+        let stream = &mut self.state.streams[stream_idx];
+        stream.update_positions();
+        outcome.lost = stream
+            .balls
+            .last()
+            .is_some_and(|ball| ball.distance_along_path >= stream.curve.length());
+
+        outcome
+    }
+
+    // Create a ball that is not part of the chain yet, e.g. for the frog to shoot
+    pub fn new_ball(&mut self, color: BallColor, effect: BallEffect) -> Ball {
+        self.next_ball_id += 1;
+        Ball {
+            coordinates: Point { x: 0., y: 0. },
+            is_reachable: true,
+            color,
+            effect,
+            distance_along_path: 0.,
+            id: self.next_ball_id - 1,
+        }
+    }
+
+    // True once every ball of the level has been spawned and cleared
+    pub fn is_won(&self) -> bool {
+        self.state.balls().next().is_none()
+            && self.spawners.iter().all(|spawner| spawner.remaining == 0)
+    }
+
+    // Fill the start of a curve with `count` touching balls, like when a level starts
+    pub fn fill(&mut self, stream_idx: usize, count: u32) {
+        for _ in 0..count {
+            if !self.spawn_at(stream_idx, 0.) {
+                break;
+            }
+            self.state.streams[stream_idx].push_forward_from(1);
+        }
+        self.state.streams[stream_idx].update_positions();
+    }
+
+    // Insert a ball next to the ball at `target_idx` and clear the resulting group if possible
+    pub fn insert_ball_at(
+        &mut self,
+        stream_idx: usize,
+        target_idx: usize,
+        side: InsertSide,
+        ball: Ball,
+    ) -> Vec<Ball> {
+        let idx = self.state.streams[stream_idx].insert_ball_at(target_idx, side, ball);
+        self.pop_group_at(stream_idx, idx).unwrap_or_default()
+    }
+
+    // Add a new ball at the start of the curve once there is room for it
+    fn spawn(&mut self, stream_idx: usize) {
+        let stream = &self.state.streams[stream_idx];
+        let spacing = stream.physics.ball_spacing;
+        let distance = match stream.balls.first() {
+            Some(ball) if ball.distance_along_path >= spacing => ball.distance_along_path - spacing,
+            Some(_) => return,
+            None => 0.,
+        };
+        self.spawn_at(stream_idx, distance);
+    }
+
+    fn spawn_at(&mut self, stream_idx: usize, distance_along_path: f32) -> bool {
+        let color = match self.spawners.get_mut(stream_idx) {
+            Some(spawner) if spawner.remaining > 0 => {
+                spawner.remaining -= 1;
+                spawner.next_color()
+            }
+            _ => return false,
+        };
+
+        let ball = Ball {
+            distance_along_path,
+            ..self.new_ball(color, BallEffect::None)
+        };
+        self.state.streams[stream_idx].balls.insert(0, ball);
+        true
+    }
+
+    // Pop the group at `idx` and apply the effects of the popped balls
+    fn pop_group_at(&mut self, stream_idx: usize, idx: usize) -> Option<Vec<Ball>> {
+        let stream = &mut self.state.streams[stream_idx];
+        let mut effects = vec![];
+        let popped = stream.pop_group_at(idx, &mut effects)?;
+        if effects.contains(&BallEffect::Reverse) {
+            stream.backwards_time_left = REVERSE_FRAMES;
+        }
+        if effects.contains(&BallEffect::Slow) {
+            stream.slowed_time_left = SLOW_FRAMES;
+        }
+
+        Some(popped)
+    }
+}
+
+// Fixtures shared with the tests of the bots
+
+// A ball that isn't on any curve yet, e.g. one the frog holds
+#[cfg(test)]
+pub(crate) fn loose_ball(id: u32, color: BallColor) -> Ball {
+    Ball {
+        coordinates: Point { x: 0., y: 0. },
+        is_reachable: true,
+        color,
+        effect: BallEffect::None,
+        distance_along_path: 0.,
+        id,
+    }
+}
+
+// Touching balls of the given colors on a straight curve 1000 units long, along the x axis.
+// The first one is `start` units along it, and ball i has id i
+#[cfg(test)]
+pub(crate) fn straight_stream(colors: &[BallColor], start: f32, forward_speed: f32) -> BallStream {
+    let points = (0..1000).map(|x| Point { x: x as f32, y: 0. }).collect();
+    let mut stream = BallStream {
+        balls: colors
+            .iter()
+            .enumerate()
+            .map(|(i, &color)| Ball {
+                distance_along_path: start + i as f32 * BALL_SPACING,
+                ..loose_ball(i as u32, color)
+            })
+            .collect(),
+        curve: Curve::from_points(points, vec![false; 1000]),
+        forward_speed,
+        ..BallStream::new()
+    };
+    stream.update_positions();
+    stream
+}
+
+#[cfg(test)]
+pub(crate) fn straight_state(colors: &[BallColor], start: f32, forward_speed: f32) -> GameState {
+    GameState {
+        streams: vec![straight_stream(colors, start, forward_speed)],
+        ..GameState::new()
+    }
+}
+
+#[test]
+fn insertion_clears_group() {
+    use BallColor::*;
+    let mut sim = Simulator::new(straight_state(&[Blue, Red, Red, Green], 100., 1.));
+
+    let ball = sim.new_ball(Red, BallEffect::None);
+    let cleared = sim.insert_ball_at(0, 1, InsertSide::End, ball);
+    assert_eq!(3, cleared.len());
+    assert_eq!(2, sim.state.streams[0].balls.len());
+    assert_eq!(2, sim.state.streams[0].segments().len());
+
+    // The front segment is stopped while the back one keeps being pushed
+    sim.tick();
+    assert_eq!(101., sim.state.streams[0].balls[0].distance_along_path);
+    assert_eq!(
+        100. + 4. * BALL_SPACING,
+        sim.state.streams[0].balls[1].distance_along_path
+    );
+}
+
+#[test]
+fn pull_back_combo() {
+    use BallColor::*;
+    let mut sim = Simulator::new(straight_state(
+        &[Blue, Green, Red, Red, Green, Green],
+        100.,
+        1.,
+    ));
+
+    let ball = sim.new_ball(Red, BallEffect::None);
+    let cleared = sim.insert_ball_at(0, 2, InsertSide::Start, ball);
+    assert_eq!(3, cleared.len());
+
+    // The greens on both sides of the gap attract each other then pop
+    let outcome = sim.advance(100);
+    assert_eq!(3, outcome.cleared.len());
+    assert_eq!(1, outcome.chains);
+    assert_eq!(1, sim.state.streams[0].balls.len());
+    assert!(!outcome.lost);
+}
+
+#[test]
+fn cascade_prediction() {
+    use BallColor::*;
+    let stream = straight_stream(&[Blue, Green, Green, Red, Red, Green, Blue, Blue], 100., 1.);
+    let mut sim = Simulator::new(GameState {
+        streams: vec![stream.clone()],
+        ..GameState::new()
+    });
+
+    let ball = sim.new_ball(Red, BallEffect::None);
+    let cascade = stream.predict_shot(3, InsertSide::Start, ball);
+    assert_eq!(vec![3, 3, 3], cascade.popped_per_step());
+    assert_eq!(2, cascade.depth());
+
+    // The simulation gets to the same outcome, one step at a time
+    let mut popped = sim.insert_ball_at(0, 3, InsertSide::Start, ball).len();
+    popped += sim.advance(200).cleared.len();
+    assert_eq!(cascade.popped(), popped);
+    assert!(sim.state.streams[0].balls.is_empty());
+}
+
+#[test]
+fn derived_generators_are_unrelated() {
+    let first = |seed, purpose, index| Rng::derive(seed, purpose, index).next_u64();
+    assert_eq!(first(7, "deal", 0), first(7, "deal", 0));
+    // Neighbouring seeds, other purposes and other indices all start elsewhere
+    let starts = [
+        first(7, "deal", 0),
+        first(8, "deal", 0),
+        first(7, "spawn", 0),
+        first(7, "spawn", 1),
+        first(8, "spawn", 0),
+    ];
+    for (i, a) in starts.iter().enumerate() {
+        assert!(starts[i + 1..].iter().all(|b| a != b));
+    }
+}
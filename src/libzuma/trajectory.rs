@@ -0,0 +1,233 @@
+use super::*;
+
+// Size of the game area, shot balls leaving it are lost
+const SCREEN_WIDTH: f32 = 640.;
+const SCREEN_HEIGHT: f32 = 480.;
+
+// Where a shot ball meets the chain
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Impact {
+    pub stream_idx: usize,
+    pub ball_idx: usize, // Index in the stream of the ball that gets hit
+    pub point: Point,    // Position of the shot ball when it touches the chain
+    pub side: InsertSide,
+    pub travel_time: f32, // In frames, from the current position of the shot ball
+}
+
+impl Impact {
+    // True if the shot ball ends up right next to the ball at `idx` of the given stream
+    pub fn lands_next_to(&self, stream_idx: usize, idx: usize) -> bool {
+        if self.stream_idx != stream_idx {
+            return false;
+        }
+
+        self.ball_idx == idx
+            || (self.ball_idx + 1 == idx && self.side == InsertSide::End)
+            || (self.ball_idx == idx + 1 && self.side == InsertSide::Start)
+    }
+}
+
+// A ball flying in a straight line after leaving the frog
+#[derive(Clone, Copy, Debug)]
+pub struct Projectile {
+    pub ball: Ball,
+    pub position: Point,
+    pub direction: Point, // Unit vector
+    pub speed: f32,       // Per frame
+}
+
+impl Projectile {
+    pub fn new(frog: &Frog, ball: Ball, aim: Point) -> Self {
+        let direction = (aim - frog.location).unit();
+        let direction = if direction.x.is_finite() && direction.y.is_finite() {
+            direction
+        } else {
+            Point { x: 0., y: -1. }
+        };
+
+        Self {
+            ball,
+            position: frog.location,
+            direction,
+            speed: frog.ball_exit_speed,
+        }
+    }
+
+    // First chain ball met within `max_dist` of the current position.
+    // Balls in tunnels can't be hit, the shot flies over them
+    pub fn first_impact(&self, state: &GameState, max_dist: f32) -> Option<Impact> {
+        let (stream_idx, ball_idx, dist) = state
+            .streams
+            .iter()
+            .enumerate()
+            .flat_map(|(stream_idx, stream)| {
+                let radius_sq = stream.physics.shot_radius.powi(2);
+                stream
+                    .balls
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, ball)| (stream_idx, i, ball, radius_sq))
+            })
+            .filter(|(_, _, ball, _)| ball.is_reachable)
+            .filter_map(|(stream_idx, i, ball, radius_sq)| {
+                // Intersection of the path of the shot with the circle around the ball
+                let to_ball = ball.coordinates - self.position;
+                let along = to_ball.dot(&self.direction);
+                let dist_sq_to_line = to_ball.dot(&to_ball) - along.powi(2);
+                if dist_sq_to_line > radius_sq {
+                    return None;
+                }
+
+                let dist = along - (radius_sq - dist_sq_to_line).sqrt();
+                if along < 0. || dist > max_dist {
+                    return None;
+                }
+
+                Some((stream_idx, i, dist.max(0.)))
+            })
+            .min_by(|a, b| a.2.total_cmp(&b.2))?;
+
+        let point = self.position + dist * self.direction;
+        Some(Impact {
+            stream_idx,
+            ball_idx,
+            point,
+            side: state.streams[stream_idx].insert_side(ball_idx, point),
+            travel_time: dist / self.speed,
+        })
+    }
+
+    // Move the ball by one frame, stopping at the chain if it hits it
+    pub fn step(&mut self, state: &GameState) -> Option<Impact> {
+        let impact = self.first_impact(state, self.speed);
+        self.position = match impact {
+            Some(impact) => impact.point,
+            None => self.position + self.speed * self.direction,
+        };
+
+        impact
+    }
+
+    pub fn is_off_screen(&self) -> bool {
+        let margin = BALL_SPACING;
+        self.position.x < -margin
+            || self.position.y < -margin
+            || self.position.x > SCREEN_WIDTH + margin
+            || self.position.y > SCREEN_HEIGHT + margin
+    }
+}
+
+// Where the active ball of the frog would hit the chain if shot towards `aim`,
+// assuming the chain doesn't move while the ball flies
+pub fn trace_shot(frog: &Frog, state: &GameState, aim: Point) -> Option<Impact> {
+    Projectile::new(frog, frog.active_ball, aim).first_impact(state, f32::MAX)
+}
+
+// The state once every ball in flight has landed and cleared what it could,
+// assuming the chain doesn't move while they fly
+pub fn land_projectiles(state: &GameState) -> GameState {
+    let mut landed = GameState {
+        projectiles: vec![],
+        ..state.clone()
+    };
+
+    let mut projectiles = state.projectiles.clone();
+    projectiles.sort_by_cached_key(|projectile| {
+        projectile
+            .first_impact(state, f32::MAX)
+            .map_or(u32::MAX, |impact| impact.travel_time as u32)
+    });
+    for projectile in projectiles {
+        let Some(impact) = projectile.first_impact(&landed, f32::MAX) else {
+            continue;
+        };
+
+        let stream = &mut landed.streams[impact.stream_idx];
+        let idx = stream.insert_ball_at(impact.ball_idx, impact.side, projectile.ball);
+        stream.clear_cascade_at(idx);
+    }
+
+    landed
+}
+
+#[test]
+fn shot_hits_first_ball_in_the_way() {
+    let ball = |x: f32, y: f32, distance_along_path: f32| Ball {
+        coordinates: Point { x, y },
+        is_reachable: true,
+        color: BallColor::Red,
+        effect: BallEffect::None,
+        distance_along_path,
+        id: distance_along_path as u32,
+    };
+    let state = GameState {
+        streams: vec![BallStream {
+            balls: vec![ball(100., 100., 0.), ball(100., 200., 100.)],
+            ..BallStream::new()
+        }],
+        ..GameState::new()
+    };
+    let frog = Frog {
+        location: Point { x: 100., y: 400. },
+        destination: Point { x: 100., y: 400. },
+        active_ball: ball(0., 0., 0.),
+        next_ball: ball(0., 0., 0.),
+        ball_exit_speed: 10.,
+    };
+
+    let impact = trace_shot(&frog, &state, Point { x: 100., y: 0. }).unwrap();
+    assert_eq!(1, impact.ball_idx);
+    assert_eq!(Point { x: 100., y: 232. }, impact.point);
+    assert_eq!(16.8, impact.travel_time);
+
+    // Balls in tunnels are flown over
+    let mut tunnel_state = state.clone();
+    tunnel_state.streams[0].balls[1].is_reachable = false;
+    let impact = trace_shot(&frog, &tunnel_state, Point { x: 100., y: 0. }).unwrap();
+    assert_eq!(0, impact.ball_idx);
+}
+
+#[test]
+fn flying_balls_land_in_the_chain() {
+    let ball = |color: BallColor, distance_along_path: f32| Ball {
+        coordinates: Point { x: 0., y: 0. },
+        is_reachable: true,
+        color,
+        effect: BallEffect::None,
+        distance_along_path,
+        id: distance_along_path as u32,
+    };
+    let points = (0..1000).map(|x| Point { x: x as f32, y: 0. }).collect();
+    let mut stream = BallStream {
+        balls: vec![
+            ball(BallColor::Blue, 100.),
+            ball(BallColor::Red, 132.),
+            ball(BallColor::Red, 164.),
+        ],
+        curve: Curve::from_points(points, vec![false; 1000]),
+        ..BallStream::new()
+    };
+    stream.update_positions();
+    let frog = Frog {
+        location: Point { x: 164., y: 300. },
+        destination: Point { x: 164., y: 300. },
+        active_ball: ball(BallColor::Red, 1.),
+        next_ball: ball(BallColor::Red, 2.),
+        ball_exit_speed: 10.,
+    };
+    let state = GameState {
+        streams: vec![stream],
+        projectiles: vec![Projectile::new(
+            &frog,
+            frog.active_ball,
+            Point { x: 164., y: 0. },
+        )],
+        ..GameState::new()
+    };
+
+    // The red ball in flight completes the group of reds
+    let landed = land_projectiles(&state);
+    assert!(landed.projectiles.is_empty());
+    assert_eq!(1, landed.streams[0].balls.len());
+    assert_eq!(BallColor::Blue, landed.streams[0].balls[0].color);
+}
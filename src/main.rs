@@ -1,5 +1,9 @@
 mod bot;
+mod headless;
 mod mem_reader;
+mod optimize;
+mod recording;
+use iced::futures::channel::oneshot;
 use iced::{
     executor,
     widget::{
@@ -9,17 +13,134 @@ use iced::{
     Application, Color, Command, Element, Length, Rectangle, Settings, Subscription, Theme,
 };
 use libwmctl::WmCtl;
+use mem_reader::ZumaReadError;
+use std::collections::HashMap;
 mod libzuma;
 
 fn main() -> iced::Result {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("simulate") => headless::run(&args[1..]),
+        Some("optimize") => optimize::run(&args[1..]),
+        Some("curve") => headless::make_curve(&args[1..]),
+        Some("inspect") => match args.get(1) {
+            Some(dir) => inspect_dump(std::path::Path::new(dir)),
+            None => Err("Usage: zum-ai-steam inspect <dump directory>".to_string()),
+        },
+        Some("replay") => match args.get(1) {
+            Some(path) => match load_replay(path) {
+                Ok(replay) => return run_interface(Some(replay)),
+                Err(err) => Err(err),
+            },
+            None => Err("Usage: zum-ai-steam replay <recording.zrec>".to_string()),
+        },
+        _ => return run_interface(None),
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn run_interface(replay: Option<recording::Replay>) -> iced::Result {
     AiInterface::run(Settings {
+        flags: replay,
         antialiasing: true,
         ..Settings::default()
     })
 }
 
+fn load_replay(path: &str) -> Result<recording::Replay, String> {
+    match recording::Replay::load(path) {
+        Ok(replay) if replay.is_empty() => Err(format!("Recording {} has no game states", path)),
+        Ok(replay) => Ok(replay),
+        Err(err) => Err(format!("Could not read recording {}: {}", path, err)),
+    }
+}
+
+// Print what the reader finds in a memory dump
+fn inspect_dump(dir: &std::path::Path) -> Result<(), String> {
+    let mut reader = mem_reader::ZumaReader::from_dump(dir)
+        .map_err(|err| format!("Could not read dump {}: {}", dir.display(), err))?;
+    if !reader.roots.scanned.is_empty() {
+        println!("Found by signature: {}", reader.roots.scanned.join(", "));
+    }
+
+    let snapshot = reader.snapshot().map_err(|err| err.to_string())?;
+    println!("Frame {}, paused: {}", snapshot.frame, snapshot.paused);
+    if let Some(level) = &snapshot.game_state.level {
+        println!(
+            "Level {} ({}), frog at {:?}, background {}",
+            level.display_name, level.id, level.frog_position, level.background
+        );
+        // The curves are loaded even when their two sections disagree, tell when they do
+        for curve in &level.curves {
+            let check =
+                libzuma::curve_file::CurveFile::load(curve.path()).and_then(|file| file.validate());
+            match check {
+                Ok(()) => println!("Curve {}: {} positions", curve.path(), curve.points.len()),
+                Err(err) => println!("Curve {}: {}", curve.path(), err),
+            }
+        }
+    }
+    for (i, stream) in snapshot.game_state.streams.iter().enumerate() {
+        let colors: Vec<_> = stream
+            .balls
+            .iter()
+            .map(|ball| format!("{:?}", ball.color))
+            .collect();
+        println!(
+            "Stream {} ({:.0} frames from the skull): {}",
+            i,
+            bot::frames_to_skull(stream),
+            colors.join(" ")
+        );
+    }
+    for projectile in &snapshot.game_state.projectiles {
+        println!("In flight: {:?}", projectile.ball.color);
+    }
+    if let Some(err) = &snapshot.projectile_error {
+        println!("Balls in flight not read: {}", err);
+    }
+    if let Some(err) = &snapshot.level_error {
+        println!("Level not read: {}", err);
+    }
+    match snapshot.frog {
+        Some(frog) => println!(
+            "Frog at {:?}, holding {:?} then {:?}",
+            frog.location, frog.active_ball.color, frog.next_ball.color
+        ),
+        None => println!("No frog"),
+    }
+
+    Ok(())
+}
+
 const BACK_TO_MENU_COORDS: libzuma::Point = libzuma::Point { x: 320., y: 360. };
 const NEW_GAME_COORDS: libzuma::Point = libzuma::Point { x: 320., y: 450. };
+// Time between two attempts at attaching again after the game was lost
+const REATTACH_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+// In ms
+const DEFAULT_SHOOT_FREQUENCY: u32 = 250;
+// Parameters of the bots, loaded at startup when it exists
+const PROFILE_PATH: &str = "bot-profile.txt";
+
+#[derive(Clone, Copy, Debug, Default)]
+struct LevelStats {
+    shots: u32,
+    losses: u32,
+}
+
+// A move a bot came up with on its own thread, along with the bot once it was done
+#[derive(Clone, Debug)]
+pub struct Thought {
+    bot: Box<dyn bot::Strategy>,
+    bot_move: bot::BotMove,
+    frog: libzuma::Frog,
+    frame: u32, // Of the snapshot it thought about
+    time: std::time::Duration,
+}
 
 #[derive(Clone, Debug)]
 pub enum Message {
@@ -27,26 +148,57 @@ pub enum Message {
     EnabledChanged(bool),
     AutoResetChanged(bool),
     ShootFreqChanged(u32),
-    ModeChanged(bot::BotMode),
+    ThinkTimeChanged(u32),
+    ModeChanged(bot::StrategyInfo),
+    ParamChanged(usize, usize, f32), // Index of the parameter set, of the parameter, and value
+    ResetParams,
+    SaveProfile,
+    LoadProfile,
+    RecordChanged(bool),
+    DumpMemory,
+    DumpFinished(Result<String, String>), // Directory of the dump, or why it failed
+    ReplayFrameChanged(u32),
+    ReplayPausedChanged(bool),
     TryAttach,
     UpdateZumaGameState,
     RefreshCanvas,
     PlayBot,
+    BotThought(Result<Thought, oneshot::Canceled>),
 }
 
 pub struct AiInterface {
     attached: Option<bool>,
     zuma_reader: mem_reader::ZumaReader,
+    snapshot: mem_reader::Snapshot, // Last state read from the game or the replay
+    read_error: Option<ZumaReadError>,
+    next_attach_attempt: Option<std::time::Instant>,
     bot_move: bot::BotMove,
+    shots: bot::ShotMemo, // In case the balls in flight can't be read
+
+    recorder: Option<recording::Recorder>,
+    recording_status: String,
+    dump_status: String,
+    dumping: bool,
+    profile: bot::Profile,
+    profile_status: String,
+
+    // When set, states come from a recording instead of the game
+    replay: Option<recording::Replay>,
+    replay_frame: u32,
+    replay_paused: bool,
+    recorded_move: bot::BotMove,
 
     win_manager: WmCtl,
     win_coords: Option<(i32, i32)>,
 
     enabled: bool,
     auto_reset: bool,
-    shoot_frequency: u32, // in ms
-    mode: bot::BotMode,
-    memo: Vec<bot::Shot>,
+    shoot_frequency: u32,               // in ms
+    think_time: u32,                    // in ms, how long the bots that can use it think
+    thinking: bool,                     // A bot is thinking on another thread
+    strategies: Vec<bot::StrategyInfo>, // Every bot that can be picked
+    mode: bot::StrategyInfo,
+    bots: HashMap<&'static str, Box<dyn bot::Strategy>>, // Bots used so far, by id
 
     // Time that the bot took to play/think its move
     bot_time_mem_read: std::time::Duration,
@@ -54,6 +206,11 @@ pub struct AiInterface {
     bot_time_play: std::time::Duration,
     bot_time_total: std::time::Duration,
 
+    // By level id
+    level_id: Option<String>, // Level of the last state that had one
+    level_stats: HashMap<String, LevelStats>,
+    level_modes: HashMap<String, bot::StrategyInfo>, // Mode last picked on each level
+
     graphics: canvas::Cache,
 }
 
@@ -61,29 +218,61 @@ impl Application for AiInterface {
     type Executor = executor::Default;
     type Message = Message;
     type Theme = Theme;
-    type Flags = ();
+    type Flags = Option<recording::Replay>;
 
-    fn new(_flags: ()) -> (Self, Command<Message>) {
-        (
-            Self {
-                attached: None,
-                zuma_reader: mem_reader::ZumaReader::new(),
-                bot_move: bot::BotMove::Nothing,
-                win_manager: WmCtl::connect().unwrap(),
-                win_coords: None,
-                enabled: true,
-                auto_reset: false,
-                shoot_frequency: 250,
-                mode: bot::BotMode::ColorBot,
-                memo: vec![],
-                bot_time_mem_read: std::time::Duration::from_secs(0),
-                bot_time_think: std::time::Duration::from_secs(0),
-                bot_time_play: std::time::Duration::from_secs(0),
-                bot_time_total: std::time::Duration::from_secs(0),
-                graphics: Default::default(),
-            },
-            Command::none(),
-        )
+    fn new(replay: Self::Flags) -> (Self, Command<Message>) {
+        let strategies = bot::StrategyInfo::all();
+        let mut interface = Self {
+            attached: None,
+            zuma_reader: mem_reader::ZumaReader::new(),
+            snapshot: mem_reader::Snapshot::new(),
+            read_error: None,
+            next_attach_attempt: None,
+            bot_move: bot::BotMove::Nothing,
+            shots: bot::ShotMemo::default(),
+            recorder: None,
+            recording_status: "".into(),
+            dump_status: "".into(),
+            dumping: false,
+            profile: bot::Profile::default(),
+            profile_status: "".into(),
+            replay,
+            replay_frame: 0,
+            replay_paused: false,
+            recorded_move: bot::BotMove::Nothing,
+            win_manager: WmCtl::connect().unwrap(),
+            win_coords: None,
+            enabled: true,
+            auto_reset: false,
+            shoot_frequency: DEFAULT_SHOOT_FREQUENCY,
+            // Half of the time between two shots, the rest is for reading the memory and clicking
+            think_time: DEFAULT_SHOOT_FREQUENCY / 2,
+            thinking: false,
+            mode: strategies[0],
+            bots: strategies
+                .iter()
+                .map(|strategy| (strategy.id, (strategy.make)()))
+                .collect(),
+            strategies,
+            bot_time_mem_read: std::time::Duration::from_secs(0),
+            bot_time_think: std::time::Duration::from_secs(0),
+            bot_time_play: std::time::Duration::from_secs(0),
+            bot_time_total: std::time::Duration::from_secs(0),
+
+            level_id: None,
+            level_stats: HashMap::new(),
+            level_modes: HashMap::new(),
+            graphics: Default::default(),
+        };
+        if std::path::Path::new(PROFILE_PATH).exists() {
+            interface.load_profile();
+        }
+        let command = match interface.replay.is_some() {
+            true => interface.show_replay_frame(0),
+            false => Command::none(),
+        };
+
+        (interface, command)
     }
 
     fn title(&self) -> String {
@@ -92,30 +281,127 @@ impl Application for AiInterface {
 
     fn update(&mut self, event: Message) -> Command<Message> {
         match event {
-            Message::TryAttach => match self.zuma_reader.find_zuma_process() {
-                Some(_) => self.attached = Some(true),
-                None => self.attached = Some(false),
-            },
+            Message::TryAttach => self.try_attach(),
             Message::AttachedChanged(new_attached) => {
                 self.attached = Some(new_attached);
             }
             Message::EnabledChanged(state) => self.enabled = state,
             Message::AutoResetChanged(state) => self.auto_reset = state,
             Message::ShootFreqChanged(freq) => self.shoot_frequency = freq,
-            Message::ModeChanged(mode) => self.mode = mode,
+            Message::ThinkTimeChanged(time) => self.think_time = time,
+            Message::ParamChanged(set_idx, param_idx, value) => {
+                if let Some(bot) = self.bots.get_mut(self.mode.id) {
+                    if let Some(set) = bot.param_sets_mut().into_iter().nth(set_idx) {
+                        set.set(param_idx, value);
+                    }
+                }
+            }
+            Message::ResetParams => {
+                self.bots.insert(self.mode.id, (self.mode.make)());
+            }
+            Message::SaveProfile => {
+                for bot in self.bots.values() {
+                    self.profile.store(bot.as_ref());
+                }
+                self.profile_status = match self.profile.save(PROFILE_PATH) {
+                    Ok(()) => format!("Saved to {}", PROFILE_PATH),
+                    Err(err) => format!("Could not save: {}", err),
+                };
+            }
+            Message::LoadProfile => self.load_profile(),
+            Message::ModeChanged(mode) => {
+                self.mode = mode;
+                if let Some(level_id) = &self.level_id {
+                    self.level_modes.insert(level_id.clone(), mode);
+                }
+            }
+            Message::RecordChanged(true) => {
+                let path = format!(
+                    "recording-{}.zrec",
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs()
+                );
+                match recording::Recorder::create(&path) {
+                    Ok(recorder) => {
+                        self.recorder = Some(recorder);
+                        self.recording_status = format!("Recording to {}", path);
+                    }
+                    Err(err) => self.recording_status = format!("Could not record: {}", err),
+                }
+            }
+            Message::RecordChanged(false) => {
+                self.recorder = None;
+                self.recording_status = "".into();
+            }
+            Message::DumpMemory => {
+                let dir = format!(
+                    "dump-{}",
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs()
+                );
+                self.dumping = true;
+                self.dump_status = format!("Dumping to {}...", dir);
+                // The game and the bot keep going while the dump is written
+                let dump = self.zuma_reader.dump();
+                let (sender, receiver) = oneshot::channel();
+                std::thread::spawn(move || {
+                    let result = dump(std::path::Path::new(&dir)).map(|()| dir);
+                    let _ = sender.send(result.map_err(|err| err.to_string()));
+                });
+                return Command::perform(receiver, |result| {
+                    Message::DumpFinished(result.unwrap_or_else(|_| Err("Dump stopped".into())))
+                });
+            }
+            Message::DumpFinished(result) => {
+                self.dumping = false;
+                self.dump_status = match result {
+                    Ok(dir) => format!("Dumped to {}", dir),
+                    Err(err) => format!("Could not dump: {}", err),
+                };
+            }
+            Message::ReplayFrameChanged(frame) => return self.show_replay_frame(frame),
+            Message::ReplayPausedChanged(paused) => self.replay_paused = paused,
             Message::UpdateZumaGameState => {
-                if let Some(true) = self.attached {
-                    self.zuma_reader.update_frog_follow_eyes();
-                    self.zuma_reader.update_balls();
-                    self.zuma_reader.update_mouse_coords();
-                    self.zuma_reader.update_frog();
+                // The bot plays on the snapshot it thought about once it is done
+                if let (Some(true), false) = (self.attached, self.thinking) {
+                    // Each read stands on its own, a setting that can't be read doesn't
+                    // keep the balls from being shown. An error of the snapshot is shown first
+                    let eyes = self.zuma_reader.update_frog_follow_eyes();
+                    let mouse = self.zuma_reader.update_mouse_coords();
+                    let result = self
+                        .zuma_reader
+                        .snapshot()
+                        .map(|snapshot| self.snapshot = snapshot);
+                    self.handle_read_result(result.and(eyes).and(mouse));
+                    self.update_level();
                 }
             }
             Message::RefreshCanvas => {
                 self.graphics.clear();
             }
             Message::PlayBot => {
-                if let Some(true) = self.attached {
+                if self.thinking {
+                    return Command::none();
+                }
+                if let Some(replay) = &self.replay {
+                    if !self.replay_paused {
+                        let last_frame = replay.len() as u32 - 1;
+                        self.replay_paused = self.replay_frame + 1 >= last_frame;
+                        return self.show_replay_frame((self.replay_frame + 1).min(last_frame));
+                    }
+                } else if self.attached != Some(true) {
+                    // Try to find the game again after losing it
+                    if self
+                        .next_attach_attempt
+                        .is_some_and(|time| std::time::Instant::now() >= time)
+                    {
+                        self.try_attach();
+                    }
+                } else {
                     let before = std::time::Instant::now();
 
                     // Find the zuma
@@ -131,56 +417,48 @@ impl Application for AiInterface {
                         self.win_coords = Some((zuma_win_x, zuma_win_y))
                     }
 
-                    self.zuma_reader.update_balls();
-                    self.zuma_reader.update_frog();
-                    if !self.enabled || self.zuma_reader.frog.is_none() {
+                    let result = self
+                        .zuma_reader
+                        .snapshot()
+                        .map(|snapshot| self.snapshot = snapshot);
+                    if !self.handle_read_result(result) {
                         return Command::none();
                     }
-
+                    self.update_level();
                     self.bot_time_mem_read = before.elapsed();
-                    let bot_shot = bot::suggest_shot(
-                        &self.zuma_reader.frog.unwrap(),
-                        &self.zuma_reader.game_state,
-                        self.mode,
-                        &mut self.memo,
-                    );
-                    self.bot_move = bot_shot;
-
-                    self.bot_time_think = before.elapsed() - self.bot_time_mem_read;
 
-                    self.zuma_reader.update_paused();
-                    if self.zuma_reader.paused {
-                        if self.auto_reset && self.zuma_reader.game_state.balls.is_empty() {
+                    // The balls are cleared when a level ends, which the auto reset relies on
+                    if self.snapshot.paused {
+                        if self.auto_reset && self.snapshot.game_state.balls().next().is_none() {
                             // We've lost, attempt to restart automatically
+                            if let Some(stats) = self.level_stats_mut() {
+                                stats.losses += 1;
+                            }
                             self.click(BACK_TO_MENU_COORDS);
                             std::thread::sleep(std::time::Duration::from_secs(1));
                             self.click(NEW_GAME_COORDS);
                         }
+                        self.record(None);
                         return Command::none();
                     }
 
-                    match bot_shot {
-                        bot::BotMove::Shoot(point) => {
-                            self.click(point);
-                        }
-                        bot::BotMove::SwapShoot(point) => {
-                            mki::Mouse::Right.click();
-                            self.click(point);
-                        }
-                        _ => {}
-                    }
-
-                    self.bot_time_play =
-                        before.elapsed() - self.bot_time_mem_read - self.bot_time_think;
-                    self.bot_time_total = before.elapsed();
+                    // Without the curves, the bot can't tell where the balls are heading
+                    let can_play = self.enabled && self.snapshot.level_error.is_none();
+                    let Some(frog) = self.snapshot.frog.filter(|_| can_play) else {
+                        self.record(None);
+                        return Command::none();
+                    };
+                    return self.think(frog);
                 }
             }
+            Message::BotThought(Ok(thought)) => return self.play_thought(thought),
+            Message::BotThought(Err(_)) => self.thinking = false,
         }
 
         Command::none()
     }
 
-    fn view(&self) -> Element<Message> {
+    fn view(&self) -> Element<'_, Message> {
         let attached_text = match self.attached {
             Some(true) => "Attached to Zuma",
             Some(false) => "Failed to attach",
@@ -188,8 +466,61 @@ impl Application for AiInterface {
         };
 
         let mut attached_options = row!(attached_text).padding(10).spacing(10);
+        if let Some(err) = &self.read_error {
+            attached_options = attached_options
+                .push(Text::new(err.to_string()).style(Color::from_rgb8(255, 0, 0)));
+        }
+        if let Some(err) = &self.snapshot.projectile_error {
+            attached_options = attached_options.push(
+                Text::new(format!("Balls in flight not read: {}", err))
+                    .style(Color::from_rgb8(255, 0, 0)),
+            );
+        }
+        if let Some(err) = &self.snapshot.level_error {
+            attached_options = attached_options.push(
+                Text::new(format!("Level not read: {}", err)).style(Color::from_rgb8(255, 0, 0)),
+            );
+        }
+
+        let bot_options = if let Some(replay) = &self.replay {
+            attached_options = row![Text::new(format!(
+                "Replaying frame {}/{}",
+                self.replay_frame + 1,
+                replay.len()
+            ))]
+            .padding(10)
+            .spacing(10);
 
-        let bot_options = if let None | Some(false) = self.attached {
+            let enabled_checkbox = checkbox("Bot enabled", self.enabled, Message::EnabledChanged);
+            let paused_checkbox =
+                checkbox("Paused", self.replay_paused, Message::ReplayPausedChanged);
+            let mode_text = Text::new("Bot mode: ".to_string());
+            let mode_choice = PickList::new(
+                self.strategies.clone(),
+                Some(self.mode),
+                Message::ModeChanged,
+            );
+            let frame_slider = Slider::new(
+                0..=replay.len() as u32 - 1,
+                self.replay_frame,
+                Message::ReplayFrameChanged,
+            );
+            let freq_text = Text::new(format!("Next frame every: {} ms", self.shoot_frequency));
+            let freqslider =
+                Slider::new(200..=1000, self.shoot_frequency, Message::ShootFreqChanged);
+            column![
+                enabled_checkbox,
+                paused_checkbox,
+                row![mode_text, mode_choice],
+                frame_slider,
+                freq_text,
+                freqslider,
+                self.think_time_view()
+            ]
+            .padding(10)
+            .spacing(10)
+            .width(Length::FillPortion(1))
+        } else if let None | Some(false) = self.attached {
             let button = Button::new("Try attaching to Zuma")
                 .padding(12)
                 .on_press(Message::TryAttach);
@@ -200,28 +531,87 @@ impl Application for AiInterface {
                 .spacing(10)
                 .width(Length::FillPortion(1))
         } else {
+            if let Ok(game_dir) = &self.zuma_reader.game_dir {
+                attached_options =
+                    attached_options.push(Text::new(format!("Game files: {}", game_dir.display())));
+            }
+            let roots = &self.zuma_reader.roots.scanned;
+            if !roots.is_empty() {
+                attached_options = attached_options.push(Text::new(format!(
+                    "Found by signature: {}",
+                    roots.join(", ")
+                )));
+            }
+
             let enabled_checkbox = checkbox("Bot enabled", self.enabled, Message::EnabledChanged);
             let reset_checkbox = checkbox("Auto reset", self.auto_reset, Message::AutoResetChanged);
+            let record_checkbox =
+                checkbox("Record", self.recorder.is_some(), Message::RecordChanged);
             let mode_text = Text::new("Bot mode: ".to_string());
-            let mode_choice =
-                PickList::new(bot::BotMode::ALL, Some(self.mode), Message::ModeChanged);
+            let mode_choice = PickList::new(
+                self.strategies.clone(),
+                Some(self.mode),
+                Message::ModeChanged,
+            );
             let freq_text = Text::new(format!("Shoot every: {} ms", self.shoot_frequency));
             let freqslider =
                 Slider::new(200..=1000, self.shoot_frequency, Message::ShootFreqChanged);
+            // Only one dump at a time, the button is disabled until it finishes
+            let mut dump_button = Button::new("Dump memory");
+            if !self.dumping {
+                dump_button = dump_button.on_press(Message::DumpMemory);
+            }
             column![
                 enabled_checkbox,
                 reset_checkbox,
+                row![record_checkbox, Text::new(&self.recording_status)].spacing(10),
+                row![dump_button, Text::new(&self.dump_status)].spacing(10),
                 row![mode_text, mode_choice],
                 freq_text,
-                freqslider
+                freqslider,
+                self.think_time_view()
             ]
             .padding(10)
             .spacing(10)
             .width(Length::FillPortion(1))
         };
 
+        let level_stats = self
+            .level_id
+            .as_ref()
+            .and_then(|level_id| self.level_stats.get(level_id))
+            .copied()
+            .unwrap_or_default();
+        let params = self
+            .bots
+            .get(self.mode.id)
+            .map(|bot| *bot.params())
+            .unwrap_or_default();
+        let danger: Vec<_> = self
+            .snapshot
+            .game_state
+            .streams
+            .iter()
+            .map(|stream| format!("{:.0}%", bot::danger(stream, &params) * 100.))
+            .collect();
+        let strategy_status = self
+            .bots
+            .get(self.mode.id)
+            .and_then(|bot| bot.status())
+            .unwrap_or_default();
         let stats = column![
             Text::new("Stats"),
+            Text::new(format!("Frame: {}", self.snapshot.frame)),
+            Text::new(match &self.snapshot.game_state.level {
+                Some(level) => format!("Level: {} ({})", level.display_name, level.id),
+                None => "Level: unknown".into(),
+            }),
+            Text::new(format!(
+                "On this level: {} shots, {} losses",
+                level_stats.shots, level_stats.losses
+            )),
+            Text::new(format!("Danger: {}", danger.join(", "))),
+            Text::new(strategy_status),
             Text::new(format!(
                 "Memory reading took: {}ms",
                 self.bot_time_mem_read.as_micros()
@@ -242,7 +632,12 @@ impl Application for AiInterface {
             .width(Length::Fixed(640.))
             .height(Length::Fixed(480.));
 
-        column![attached_options, row![stats, bot_options], ball_display].into()
+        column![
+            attached_options,
+            row![stats, bot_options, self.params_view()],
+            ball_display
+        ]
+        .into()
     }
 
     fn subscription(&self) -> Subscription<Self::Message> {
@@ -259,12 +654,296 @@ impl Application for AiInterface {
 }
 
 impl AiInterface {
+    fn try_attach(&mut self) {
+        match self.zuma_reader.find_zuma_process() {
+            Some(_) => {
+                self.attached = Some(true);
+                self.read_error = None;
+                self.next_attach_attempt = None;
+            }
+            None => {
+                self.attached = Some(false);
+                if self.next_attach_attempt.is_some() {
+                    self.next_attach_attempt = Some(std::time::Instant::now() + REATTACH_DELAY);
+                }
+            }
+        }
+    }
+
+    // Follow the level being played, and switch to the mode last picked on it
+    fn update_level(&mut self) {
+        let Some(level) = &self.snapshot.game_state.level else {
+            return;
+        };
+        if self.level_id.as_ref() == Some(&level.id) {
+            return;
+        }
+
+        self.level_id = Some(level.id.clone());
+        if let Some(&mode) = self.level_modes.get(&level.id) {
+            self.mode = mode;
+        }
+        for bot in self.bots.values_mut() {
+            bot.reset();
+        }
+        self.shots.clear();
+    }
+
+    // Ask the bot picked for a move, making it the first time it is used. A copy of it
+    // thinks on another thread so that the interface keeps responding meanwhile
+    fn think(&mut self, frog: libzuma::Frog) -> Command<Message> {
+        let budget = self.think_budget();
+        let mode = self.mode;
+        let mut strategy = self.bots.entry(mode.id).or_insert_with(mode.make).clone();
+
+        // The bot's own shots stand in for the balls in flight when none could be read
+        let snapshot = &self.snapshot;
+        let in_flight = self
+            .shots
+            .in_flight(&snapshot.game_state, strategy.params().physics());
+        let mut state = snapshot.game_state.clone();
+        if snapshot.projectile_error.is_some() || state.projectiles.is_empty() {
+            state.projectiles = in_flight;
+        }
+        let frame = snapshot.frame;
+
+        self.thinking = true;
+        let (sender, receiver) = oneshot::channel();
+        std::thread::spawn(move || {
+            let before = std::time::Instant::now();
+            let bot_move = bot::suggest_shot(&frog, &state, strategy.as_mut(), budget);
+            let _ = sender.send(Thought {
+                bot: strategy,
+                bot_move,
+                frog,
+                frame,
+                time: before.elapsed(),
+            });
+        });
+        Command::perform(receiver, Message::BotThought)
+    }
+
+    // Take back the bot that thought and play its move, unless the state it thought about
+    // was replaced in the meantime, e.g. by moving through a replay
+    fn play_thought(&mut self, thought: Thought) -> Command<Message> {
+        self.thinking = false;
+        let Thought {
+            mut bot,
+            bot_move,
+            frog,
+            frame,
+            time,
+        } = thought;
+        // The parameters changed while it thought are kept
+        if let Some(current) = self.bots.get(bot.id()) {
+            let mut params = bot::Profile::default();
+            params.store(current.as_ref());
+            params.apply(bot.as_mut());
+        }
+        self.bots.insert(bot.id(), bot);
+
+        if self.replay.is_some() {
+            if frame != self.replay_frame {
+                return self.show_replay_frame(self.replay_frame);
+            }
+            self.bot_move = bot_move;
+            self.bot_time_think = time;
+            self.bot_time_total = self.bot_time_mem_read + time;
+            return Command::none();
+        }
+
+        // The bot may have been stopped, or the game lost, while it thought
+        if !self.enabled || self.win_coords.is_none() {
+            self.record(None);
+            return Command::none();
+        }
+        let before = std::time::Instant::now();
+        self.bot_move = bot_move;
+        self.bot_time_think = time;
+
+        match bot_move {
+            bot::BotMove::Shoot(point) => {
+                self.click(point);
+            }
+            bot::BotMove::SwapShoot(point) => {
+                mki::Mouse::Right.click();
+                self.click(point);
+            }
+            bot::BotMove::MoveFrog(point) => self.move_frog(point),
+            bot::BotMove::Nothing => {}
+        }
+        if let bot::BotMove::Shoot(_) | bot::BotMove::SwapShoot(_) = bot_move {
+            if let Some(stats) = self.level_stats_mut() {
+                stats.shots += 1;
+            }
+        }
+        let physics = self.bots[self.mode.id].params().physics();
+        self.shots
+            .remember(&frog, &self.snapshot.game_state, bot_move, physics);
+        self.record(Some(bot_move));
+
+        self.bot_time_play = before.elapsed();
+        self.bot_time_total = self.bot_time_mem_read + self.bot_time_think + self.bot_time_play;
+        Command::none()
+    }
+
+    // How long the bots that keep improving with time think about each shot
+    fn think_time_view(&self) -> Element<'_, Message> {
+        column![
+            Text::new(format!("Think for: {} ms", self.think_time)),
+            Slider::new(10..=1000, self.think_time, Message::ThinkTimeChanged)
+        ]
+        .spacing(10)
+        .into()
+    }
+
+    // A slider for each parameter of the bot picked
+    fn params_view(&self) -> Element<'_, Message> {
+        let mut params = column![Text::new("Parameters")]
+            .spacing(5)
+            .padding(10)
+            .width(Length::FillPortion(1));
+        if let Some(bot) = self.bots.get(self.mode.id) {
+            for (set_idx, set) in bot.param_sets().into_iter().enumerate() {
+                for (param_idx, spec) in set.specs().iter().enumerate() {
+                    let value = set.get(param_idx);
+                    let (step, text) = match spec.integer {
+                        true => (1., format!("{}.{}: {}", set.section(), spec.name, value)),
+                        false => (
+                            (spec.max - spec.min) / 100.,
+                            format!("{}.{}: {:.2}", set.section(), spec.name, value),
+                        ),
+                    };
+                    let slider = Slider::new(spec.min..=spec.max, value, move |value| {
+                        Message::ParamChanged(set_idx, param_idx, value)
+                    })
+                    .step(step);
+                    params = params.push(Text::new(text).size(14)).push(slider);
+                }
+            }
+        }
+
+        params
+            .push(
+                row![
+                    Button::new("Defaults").on_press(Message::ResetParams),
+                    Button::new("Save profile").on_press(Message::SaveProfile),
+                    Button::new("Load profile").on_press(Message::LoadProfile),
+                ]
+                .spacing(10),
+            )
+            .push(Text::new(&self.profile_status))
+            .into()
+    }
+
+    fn load_profile(&mut self) {
+        match bot::Profile::load(PROFILE_PATH) {
+            Ok(profile) => {
+                for bot in self.bots.values_mut() {
+                    profile.apply(bot.as_mut());
+                }
+                self.profile = profile;
+                self.profile_status = format!("Loaded {}", PROFILE_PATH);
+            }
+            Err(err) => self.profile_status = format!("Could not load {}: {}", PROFILE_PATH, err),
+        }
+    }
+
+    fn level_stats_mut(&mut self) -> Option<&mut LevelStats> {
+        let level_id = self.level_id.clone()?;
+        Some(self.level_stats.entry(level_id).or_default())
+    }
+
+    // Keep the error to show it, and detach if the game is gone so that
+    // attaching is retried. Returns true if the read succeeded
+    fn handle_read_result(&mut self, result: Result<(), ZumaReadError>) -> bool {
+        let Err(err) = result else {
+            self.read_error = None;
+            return true;
+        };
+
+        if err.is_detached() {
+            self.zuma_reader.detach();
+            self.attached = Some(false);
+            self.win_coords = None;
+            self.next_attach_attempt = Some(std::time::Instant::now() + REATTACH_DELAY);
+        }
+        self.read_error = Some(err);
+        false
+    }
+
+    // Save what the bot saw, and the move it played, if recording
+    fn record(&mut self, bot_move: Option<bot::BotMove>) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+
+        if let Err(err) = recorder.record(&self.snapshot.game_state, self.snapshot.frog, bot_move) {
+            self.recorder = None;
+            self.recording_status = format!("Recording stopped: {}", err);
+        }
+    }
+
+    // Load a frame of the replay as if it was just read from the game,
+    // and let the bot decide what it would do now
+    fn show_replay_frame(&mut self, frame: u32) -> Command<Message> {
+        let Some(replay) = &self.replay else {
+            return Command::none();
+        };
+
+        let before = std::time::Instant::now();
+        let recording::ReplayFrame {
+            state,
+            frog,
+            bot_move,
+        } = replay.frame(frame as usize);
+        self.replay_frame = frame;
+        self.snapshot = mem_reader::Snapshot {
+            game_state: state,
+            frog,
+            paused: false,
+            frame,
+            projectile_error: None,
+            level_error: None,
+        };
+        self.update_level();
+        self.recorded_move = bot_move.unwrap_or(bot::BotMove::Nothing);
+        self.bot_time_mem_read = before.elapsed();
+
+        self.bot_move = bot::BotMove::Nothing;
+        match frog {
+            // The frame the bot is thinking about is replaced, it thinks again once done
+            Some(frog) if self.enabled && !self.thinking => self.think(frog),
+            _ => Command::none(),
+        }
+    }
+
+    fn think_budget(&self) -> bot::ThinkBudget {
+        bot::ThinkBudget::time(std::time::Duration::from_millis(self.think_time as u64))
+    }
+
     // click on the given point (in zuma frame of reference)
     fn click(&self, point: libzuma::Point) {
-        mki::Mouse::Left.click_at(
+        let (x, y) = self.screen_coords(point);
+        mki::Mouse::Left.click_at(x, y);
+    }
+
+    // A slider follows the mouse, while jumps are made by clicking where to go
+    fn move_frog(&self, position: libzuma::Point) {
+        let level = self.snapshot.game_state.level.as_ref();
+        if let Some(libzuma::FrogType::Slider(..)) = level.map(|level| &level.frog_type) {
+            let (x, y) = self.screen_coords(position);
+            mki::Mouse::move_to(x, y);
+        } else {
+            self.click(position);
+        }
+    }
+
+    fn screen_coords(&self, point: libzuma::Point) -> (i32, i32) {
+        (
             self.win_coords.unwrap().0 - 1 + point.x.clamp(0., 635.) as i32,
             self.win_coords.unwrap().1 - 38 + point.y.clamp(0., 475.) as i32,
-        );
+        )
     }
 }
 
@@ -284,7 +963,13 @@ impl<Message> canvas::Program<Message> for AiInterface {
                 Color::BLACK,
             );
 
-            for point in &self.zuma_reader.game_state.curve.points {
+            let curve_points = self
+                .snapshot
+                .game_state
+                .streams
+                .iter()
+                .flat_map(|stream| stream.curve.points.iter());
+            for point in curve_points {
                 frame.fill(
                     &Path::circle(
                         iced::Point {
@@ -299,7 +984,7 @@ impl<Message> canvas::Program<Message> for AiInterface {
 
             let mut reachable_balls = vec![];
 
-            if let Some(frog) = self.zuma_reader.frog {
+            if let Some(frog) = self.snapshot.frog {
                 let frog_pos = iced::Point::new(frog.location.x, frog.location.y);
                 frame.fill_text(canvas::Text {
                     content: format!("{:?}", frog.active_ball.color),
@@ -308,34 +993,57 @@ impl<Message> canvas::Program<Message> for AiInterface {
                     ..Default::default()
                 });
 
-                reachable_balls = bot::reachable_balls(&frog, &self.zuma_reader.game_state);
-
-                match self.bot_move {
-                    bot::BotMove::Shoot(bot_coords) => {
-                        let coords = iced::Point {
-                            x: bot_coords.x,
-                            y: bot_coords.y,
-                        };
-                        let line = &Path::line(frog_pos, coords);
-                        let stroke = Stroke {
-                            width: 5.,
-                            style: stroke::Style::Solid(Color::from_rgb8(255, 255, 0)),
-                            ..Stroke::default()
-                        };
-
-                        frame.stroke(line, stroke);
+                reachable_balls = bot::reachable_balls(&frog, &self.snapshot.game_state);
+
+                // The move recorded in a replay is drawn under the one the bot suggests now
+                let moves = [
+                    (self.recorded_move, Color::from_rgb8(0, 255, 255)),
+                    (self.bot_move, Color::from_rgb8(255, 255, 0)),
+                ];
+                for (bot_move, color) in moves {
+                    let stroke = Stroke {
+                        width: 5.,
+                        style: stroke::Style::Solid(color),
+                        ..Stroke::default()
+                    };
+                    match bot_move {
+                        bot::BotMove::Shoot(bot_coords) | bot::BotMove::SwapShoot(bot_coords) => {
+                            let coords = iced::Point {
+                                x: bot_coords.x,
+                                y: bot_coords.y,
+                            };
+                            frame.stroke(&Path::line(frog_pos, coords), stroke);
+                        }
+                        // Where the frog is sent
+                        bot::BotMove::MoveFrog(position) => {
+                            let coords = iced::Point {
+                                x: position.x,
+                                y: position.y,
+                            };
+                            frame.stroke(&Path::circle(coords, 25.), stroke);
+                        }
+                        bot::BotMove::Nothing => {}
                     }
-                    _ => {}
                 }
             }
 
-            for (i, ball) in self.zuma_reader.game_state.balls.iter().enumerate() {
-                draw_ball(
-                    frame,
-                    ball,
-                    Some(format!("{}", i)),
-                    reachable_balls.contains(ball),
-                );
+            for projectile in &self.snapshot.game_state.projectiles {
+                let ball = libzuma::Ball {
+                    coordinates: projectile.position,
+                    ..projectile.ball
+                };
+                draw_ball(frame, &ball, None, true);
+            }
+
+            for stream in &self.snapshot.game_state.streams {
+                for (i, ball) in stream.balls.iter().enumerate() {
+                    draw_ball(
+                        frame,
+                        ball,
+                        Some(format!("{}", i)),
+                        reachable_balls.contains(ball),
+                    );
+                }
             }
         })]
     }
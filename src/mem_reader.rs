@@ -1,15 +1,49 @@
-use std::io::{Error, ErrorKind, Read};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 
+use crate::libzuma::trajectory::Projectile;
 use crate::libzuma::*;
-use process_memory::*;
+use memory::{DumpMemory, MemorySource, Plain, ProcMemory, PAGE_SIZE};
+use signatures::Signature;
 use sysinfo::{Pid, ProcessExt, SystemExt};
 
+mod memory;
+mod signatures;
+
 const FROG_EYES_FOLLOWING_OFFSET: usize = 0x59D5FC;
 const STREAM_PARENT_OFFSETS: [usize; 2] = [0x83FE00, 0x0]; // I think this is a stack address? But it appears very consistent
 
 const MOUSE_COORDS_OFFSETS: [usize; 4] = [0x59F4A4, 0x320, 0x10, 0xE0];
 
-const GAME_LOCATION: &str = "xxx";
+// Code that loads the first address of each chain above, to find them on other builds.
+// Not checked against other builds yet, a root found with them is only used if it reads back sane
+const FROG_EYES_FOLLOWING_SIGNATURE: Signature = Signature {
+    pattern: "80 3D ?? ?? ?? ?? 00 74 ?? 8B 86 ?? ?? ?? ?? D9 46",
+    operand: 2,
+};
+const STREAM_PARENT_SIGNATURE: Signature = Signature {
+    pattern: "8B 0D ?? ?? ?? ?? 8B 01 85 C0 74 ?? 8B 88 8C 00 00 00",
+    operand: 2,
+};
+const MOUSE_COORDS_SIGNATURE: Signature = Signature {
+    pattern: "A1 ?? ?? ?? ?? 8B 88 20 03 00 00 8B 51 10",
+    operand: 1,
+};
+
+// Most balls a stream of a plausible game has, well over what levels hold
+const MAX_STREAM_BALLS: u32 = 1000;
+// The mouse coordinates are within the window
+const MAX_MOUSE_COORDS: [u32; 2] = [640, 480];
+
+// Where to look for the game files if it wasn't started from its own directory
+const GAME_DIR_ENV: &str = "ZUMA_GAME_DIR";
+const GAME_DIR_CONFIG: &str = "zum-ai-steam/game_dir"; // In the user config directory
+const STEAM_GAME_DIR: &str = "drive_c/Program Files (x86)/Steam/steamapps/common/Zuma Deluxe";
+
+// Reads of a snapshot that may be attempted before giving up on getting one consistent frame
+const SNAPSHOT_ATTEMPTS: u32 = 5;
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -26,6 +60,16 @@ struct MemBall {
     _padding_0x68: [u8; 8],
 }
 
+// A ball shot by the frog, that keeps moving in a straight line until it lands
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct MemBullet {
+    ball: MemBall,
+    // Per frame. Guessed from the fields following the ball, not checked against the game yet
+    vel_x: f32,
+    vel_y: f32,
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 struct MemFrog {
@@ -64,7 +108,11 @@ struct MemBallLinkedListElement {
 struct Game {
     __padding_0x0: [u8; 140],
     ballstream_ptrs: [u32; 6],
-    __padding_0xa4: [u8; 24],
+    __padding_0xa4: [u8; 16],
+    // Same kind of list as the balls, with a sentinel head element.
+    // Both guessed from the fields around them, not checked against the game yet
+    ptr_bullet_list: u32,
+    bullet_count: u32,
     ballstream_count: u32,
     __padding_0xc0: [u8; 13 * 4],
     igt: u32, // igt in frames (i think)
@@ -111,25 +159,130 @@ struct MemLvlData {
     ptr_filepath_curv2: u32,
 }
 
+// Addresses the pointer chains start from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Roots {
+    pub frog_eyes_following: usize,
+    pub stream_parent: usize,
+    pub mouse_coords: usize,
+    pub scanned: Vec<&'static str>, // Roots that were found by signature instead of the constants
+}
+
+impl Roots {
+    // The ones of the Steam build
+    pub fn constants() -> Self {
+        Self {
+            frog_eyes_following: FROG_EYES_FOLLOWING_OFFSET,
+            stream_parent: STREAM_PARENT_OFFSETS[0],
+            mouse_coords: MOUSE_COORDS_OFFSETS[0],
+            scanned: vec![],
+        }
+    }
+}
+
+unsafe impl Plain for MemBall {}
+unsafe impl Plain for MemBullet {}
+unsafe impl Plain for MemFrog {}
+unsafe impl Plain for MemBallLinkedList {}
+unsafe impl Plain for MemBallLinkedListElement {}
+unsafe impl Plain for Game {}
+unsafe impl Plain for MemBallStream {}
+unsafe impl Plain for MemLvlData {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ZumaReadError {
+    NotAttached,
+    ProcessGone,
+    BadPointer(&'static str, usize), // What was being read, and the address it was read at
+    InvalidColor(u32),
+    InvalidEffect(u32),
+    CurveMissing(String),
+    FrameChanged, // The game kept moving to the next frame while being read
+}
+
+impl std::fmt::Display for ZumaReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ZumaReadError::NotAttached => write!(f, "Not attached to the game"),
+            ZumaReadError::ProcessGone => write!(f, "The game process exited"),
+            ZumaReadError::BadPointer(what, address) => {
+                write!(f, "Could not read the {} at 0x{:X}", what, address)
+            }
+            ZumaReadError::InvalidColor(color) => write!(f, "Invalid ball color 0x{:X}", color),
+            ZumaReadError::InvalidEffect(effect) => {
+                write!(f, "Invalid ball effect 0x{:X}", effect)
+            }
+            ZumaReadError::CurveMissing(curve) => write!(f, "Missing curve {}", curve),
+            ZumaReadError::FrameChanged => write!(f, "The game changed during every read"),
+        }
+    }
+}
+
+impl ZumaReadError {
+    // The reader needs to attach again before any read can succeed
+    pub fn is_detached(&self) -> bool {
+        matches!(
+            self,
+            ZumaReadError::NotAttached | ZumaReadError::ProcessGone
+        )
+    }
+}
+
+// Everything the bot needs, read from a single game frame
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub game_state: GameState,
+    pub frog: Option<Frog>,
+    pub paused: bool,
+    pub frame: u32, // In-game time in frames, or the frame index of a replay
+    // Why the balls in flight couldn't be read, the rest of the snapshot is still used without them
+    pub projectile_error: Option<ZumaReadError>,
+    // Why the level is missing although a game is being played, e.g. its curves weren't found.
+    // The balls are still read, on empty curves
+    pub level_error: Option<ZumaReadError>,
+}
+
+impl Snapshot {
+    pub fn new() -> Self {
+        Self {
+            game_state: GameState::new(),
+            frog: None,
+            paused: true,
+            frame: 0,
+            projectile_error: None,
+            level_error: None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ZumaReader {
-    pub process_handle: Option<ProcessHandle>,
-    pub game_state: GameState,
+    memory: Option<Box<dyn MemorySource>>,
+    pid: Option<Pid>,
     pub frog_follow_eyes: Option<bool>,
     pub mouse_coords: Option<(u32, u32)>,
-    pub frog: Option<Frog>,
-    pub paused: bool,
+    pub game_dir: Result<PathBuf, String>, // Or why it couldn't be found
+    pub roots: Roots,
+    curves: Vec<Curve>, // Kept between snapshots, files are only read again on change
+    // Where the frog of the current level was seen standing, to tell how it moves
+    frog_positions: Vec<Point>,
+    frog_positions_level: String,
+    started: Instant,
 }
 
 impl ZumaReader {
     pub fn new() -> Self {
         Self {
-            process_handle: None,
-            game_state: GameState::new(),
+            memory: None,
+            pid: None,
             frog_follow_eyes: None,
             mouse_coords: None,
-            frog: None,
-            paused: true,
+            game_dir: Err("not attached".into()),
+            roots: Roots::constants(),
+            curves: vec![],
+            frog_positions: vec![],
+            frog_positions_level: String::new(),
+            started: Instant::now(),
         }
     }
 
@@ -144,174 +297,509 @@ impl ZumaReader {
                     .any(|command| command.contains(&"popcapgame1".to_string()))
             })?;
 
-        self.process_handle = process
-            .pid()
-            .try_into_process_handle()
+        self.memory = ProcMemory::open(process.pid())
             .ok()
-            .and_then(|p| Some(p.set_arch(Architecture::Arch32Bit)));
+            .map(|memory| Box::new(memory) as Box<dyn MemorySource>);
+        self.pid = Some(process.pid());
 
         // self.update_frog_struct();
 
+        self.roots = self.scan_roots();
+        self.game_dir = find_game_dir(Some(process.pid()));
+
         Some(process.pid())
     }
 
-    fn get_frog_follow_eyes(&self) -> Option<bool> {
-        DataMember::new_offset(self.process_handle?, vec![FROG_EYES_FOLLOWING_OFFSET])
-            .read()
-            .ok()
+    // Read a dump saved with `dump` instead of the running game
+    pub fn from_dump(dir: &Path) -> std::io::Result<Self> {
+        let mut reader = Self::new();
+        reader.memory = Some(Box::new(DumpMemory::load(dir)?));
+        reader.roots = reader.scan_roots();
+        reader.game_dir = find_game_dir(None);
+        Ok(reader)
     }
 
-    fn get_mouse_coords(&self) -> Option<(u32, u32)> {
-        DataMember::new_offset(self.process_handle?, MOUSE_COORDS_OFFSETS.to_vec())
-            .read()
-            .ok()
+    // Save the memory of the game, to look into it or test the reader against it later.
+    // Reading every region takes a while, so the dump owns what it needs to run on another thread
+    pub fn dump(&self) -> impl FnOnce(&Path) -> std::io::Result<()> + Send + 'static {
+        let pid = self.pid;
+        move |dir| {
+            let pid = pid.ok_or_else(|| std::io::Error::other("Not connected to ZUMA process"))?;
+            ProcMemory::open(pid)?.dump(dir)
+        }
+    }
+
+    pub fn detach(&mut self) {
+        self.memory = None;
+        self.pid = None;
     }
 
-    fn read_ball(&self, ball_address: Vec<usize>) -> Option<Ball> {
-        let mem_ball: MemBall = DataMember::new_offset(self.process_handle?, ball_address)
-            .read()
-            .ok()?;
+    // Read the value at the end of a chain of offsets. When the read fails,
+    // tell apart a process that exited from a pointer that leads nowhere, e.g. while a level loads
+    fn read<T: Plain>(&self, what: &'static str, offsets: Vec<usize>) -> Result<T, ZumaReadError> {
+        let memory = self.memory.as_ref().ok_or(ZumaReadError::NotAttached)?;
+        let address = offsets.last().copied().unwrap_or_default();
 
-        Some(Ball {
-            color: number_to_color(mem_ball.color).ok()?,
-            coordinates: Point {
-                x: mem_ball.x,
-                y: mem_ball.y,
+        memory.read_chain(&offsets).ok_or_else(|| {
+            if memory.is_alive() {
+                ZumaReadError::BadPointer(what, address)
+            } else {
+                ZumaReadError::ProcessGone
+            }
+        })
+    }
+
+    // Check that the constants lead to something sane, and when one doesn't,
+    // try the roots found by signature in the code of the game in turn.
+    // The game only exists during a level, so attaching from the menu keeps its constant
+    fn scan_roots(&mut self) -> Roots {
+        let mut roots = Roots::constants();
+        let proc = ScannableProc {
+            memory: self.memory.as_deref(),
+            reader_cursor: signatures::IMAGE_START,
+        };
+        let Ok(image) = signatures::read_image(proc) else {
+            return roots;
+        };
+        let mut scan =
+            |root: &mut usize, name, signature: Signature, is_sane: &dyn Fn(usize) -> bool| {
+                if is_sane(*root) {
+                    return;
+                }
+                if let Some(found) = signature
+                    .find_all(&image, signatures::IMAGE_START)
+                    .find(|&found| is_sane(found))
+                {
+                    *root = found;
+                    roots.scanned.push(name);
+                }
+            };
+
+        scan(
+            &mut roots.frog_eyes_following,
+            "frog eyes setting",
+            FROG_EYES_FOLLOWING_SIGNATURE,
+            &|root| {
+                let eyes: Result<u8, _> = self.read("frog eyes setting", vec![root]);
+                eyes.is_ok_and(|eyes| eyes <= 1)
+            },
+        );
+        scan(
+            &mut roots.stream_parent,
+            "game",
+            STREAM_PARENT_SIGNATURE,
+            &|root| self.is_plausible_game(root),
+        );
+        scan(
+            &mut roots.mouse_coords,
+            "mouse coordinates",
+            MOUSE_COORDS_SIGNATURE,
+            &|root| {
+                let coords: Result<[u32; 2], _> =
+                    self.read("mouse coordinates", from_root(root, &MOUSE_COORDS_OFFSETS));
+                coords.is_ok_and(|[x, y]| x <= MAX_MOUSE_COORDS[0] && y <= MAX_MOUSE_COORDS[1])
             },
-            effect: number_to_effect(mem_ball.effect).ok()?,
-            is_reachable: true,
-            distance_along_path: mem_ball.distance_along_path,
-            id: mem_ball.global_ball_number,
+        );
+
+        roots
+    }
+
+    // Whether the root leads to a game being played: a few streams, each with
+    // a readable list of a sensible number of balls
+    fn is_plausible_game(&self, root: usize) -> bool {
+        let Ok(game) = self.read::<Game>("game", from_root(root, &STREAM_PARENT_OFFSETS)) else {
+            return false;
+        };
+        let count = game.ballstream_count as usize;
+        if count == 0 || count > game.ballstream_ptrs.len() || game.game_state > 2 {
+            return false;
+        }
+        game.ballstream_ptrs[..count].iter().all(|&ptr| {
+            self.read::<MemBallStream>("ball stream", vec![ptr as usize])
+                .is_ok_and(|stream| {
+                    stream.ball_count <= MAX_STREAM_BALLS
+                        && (stream.ball_count == 0
+                            || self
+                                .read::<MemBallLinkedList>(
+                                    "ball list",
+                                    vec![stream.ptr_ball_linked_list as usize],
+                                )
+                                .is_ok())
+                })
         })
     }
 
-    pub fn update_frog_follow_eyes(&mut self) {
-        self.frog_follow_eyes = self.get_frog_follow_eyes();
+    fn read_game(&self) -> Result<Game, ZumaReadError> {
+        self.read(
+            "game",
+            from_root(self.roots.stream_parent, &STREAM_PARENT_OFFSETS),
+        )
     }
 
-    pub fn update_balls(&mut self) {
-        self.game_state.balls.clear();
+    fn read_ball(&self, ball_address: usize) -> Result<Ball, ZumaReadError> {
+        let mem_ball: MemBall = self.read("ball", vec![ball_address])?;
 
-        let mem_stream_parent =
-            DataMember::new_offset(self.process_handle.unwrap(), STREAM_PARENT_OFFSETS.to_vec())
-                .read();
+        mem_ball_to_ball(&mem_ball)
+    }
 
-        let mem_stream_parent: Game = match mem_stream_parent {
-            Ok(thing) => thing,
-            _ => return,
-        };
+    // Balls that the frog shot and that haven't reached the chain yet
+    fn read_projectiles(&self, game: &Game) -> Result<Vec<Projectile>, ZumaReadError> {
+        let head: MemBallLinkedListElement =
+            self.read("bullet list", vec![game.ptr_bullet_list as usize])?;
 
-        // Read the balls
-        for i in 0..mem_stream_parent.ballstream_count {
-            let mem_stream: MemBallStream = DataMember::new_offset(
-                self.process_handle.unwrap(),
-                vec![mem_stream_parent.ballstream_ptrs[i as usize] as usize],
-            )
-            .read()
-            .unwrap();
-            self.game_state.forward_speed = mem_stream.balls_speed;
-            self.game_state.backwards_time_left = mem_stream.reverse_cooldown;
-
-            // Get the linked list manager thingymajig
-            let ball_linked_list: MemBallLinkedList = DataMember::new_offset(
-                self.process_handle.unwrap(),
-                vec![mem_stream.ptr_ball_linked_list as usize],
-            )
-            .read()
-            .unwrap();
-
-            // Get the balls!
-            let mut next_mem_ball = ball_linked_list.ptr_first_elem;
-            for _i in 0..mem_stream.ball_count {
-                let elem: MemBallLinkedListElement = DataMember::new_offset(
-                    self.process_handle.unwrap(),
-                    vec![next_mem_ball as usize],
-                )
-                .read()
-                .unwrap();
-                next_mem_ball = elem.ptr_next_elem;
-
-                match self.read_ball(vec![elem.ptr_ball as usize]) {
-                    Some(ball) => self.game_state.balls.push(ball),
-                    None => return,
-                };
-            }
+        let mut projectiles = vec![];
+        let mut next_elem = head.ptr_next_elem;
+        for _ in 0..game.bullet_count.min(64) {
+            let elem: MemBallLinkedListElement =
+                self.read("bullet list element", vec![next_elem as usize])?;
+            next_elem = elem.ptr_next_elem;
 
-            // Read the curve file
-            let lvl_data: MemLvlData = DataMember::new_offset(
-                self.process_handle.unwrap(),
-                vec![mem_stream.ptr_level_data as usize],
-            )
-            .read()
-            .unwrap();
-            let curv_filepath: [u8; 50] = DataMember::new_offset(
-                self.process_handle.unwrap(),
-                vec![lvl_data.ptr_filepath_curv1 as usize],
-            )
-            .read()
-            .unwrap();
-            let idx = curv_filepath.iter().position(|&e| e == 0).unwrap();
-            let path = GAME_LOCATION.to_owned()
-                + &String::from_utf8(curv_filepath[..idx].into()).unwrap()
-                + ".dat";
-            self.game_state.curve.read_from_file(path);
+            let bullet: MemBullet = self.read("bullet", vec![elem.ptr_ball as usize])?;
+            let velocity = Point {
+                x: bullet.vel_x,
+                y: bullet.vel_y,
+            };
+            // A ball that doesn't move has no direction, there is no telling where it lands
+            let direction = velocity.unit();
+            let speed = velocity.dot(&velocity).sqrt();
+            if !(speed > 0.
+                && speed.is_finite()
+                && direction.x.is_finite()
+                && direction.y.is_finite())
+            {
+                continue;
+            }
+            let ball = mem_ball_to_ball(&bullet.ball)?;
+            projectiles.push(Projectile {
+                ball,
+                position: ball.coordinates,
+                direction,
+                speed,
+            });
         }
+
+        Ok(projectiles)
     }
 
-    pub fn update_paused(&mut self) {
-        let mem_stream_parent =
-            DataMember::new_offset(self.process_handle.unwrap(), STREAM_PARENT_OFFSETS.to_vec())
-                .read();
+    pub fn update_frog_follow_eyes(&mut self) -> Result<(), ZumaReadError> {
+        self.frog_follow_eyes = None;
+        let eyes: u8 = self.read("frog eyes setting", vec![self.roots.frog_eyes_following])?;
+        self.frog_follow_eyes = Some(eyes != 0);
+        Ok(())
+    }
 
-        let mem_stream_parent: Game = match mem_stream_parent {
-            Ok(thing) => thing,
-            _ => {
-                self.paused = false;
-                return;
+    // Read the game, the balls and the frog, retrying until they all come from the same frame
+    pub fn snapshot(&mut self) -> Result<Snapshot, ZumaReadError> {
+        for _ in 0..SNAPSHOT_ATTEMPTS {
+            self.clear_cache();
+            let game = self.read_game()?;
+            let time = self.started.elapsed();
+            // Neither the balls in flight nor the frog are worth discarding the chain
+            let frog = self.read_frog(&game).ok();
+            let (level, level_error) = match self.read_level(&game, frog.as_ref()) {
+                Ok(level) => (level, None),
+                Err(err @ ZumaReadError::CurveMissing(_)) => (None, Some(err)),
+                Err(err) => return Err(err),
+            };
+            let curves = match &level {
+                Some(level) => level.curves.clone(),
+                None if level_error.is_some() => vec![Curve::new(); stream_count(&game)],
+                None => vec![],
+            };
+            let streams = self.read_streams(&game, &curves)?;
+            let (projectiles, projectile_error) = match self.read_projectiles(&game) {
+                Ok(projectiles) => (projectiles, None),
+                Err(err) => (vec![], Some(err)),
+            };
+
+            self.clear_cache();
+            if self.read_game()?.igt != game.igt {
+                continue;
             }
-        };
 
-        self.paused = mem_stream_parent.game_state != 0;
+            return Ok(Snapshot {
+                game_state: GameState {
+                    streams,
+                    projectiles,
+                    time,
+                    level,
+                },
+                frog,
+                paused: game.game_state != 0,
+                frame: game.igt,
+                projectile_error,
+                level_error,
+            });
+        }
+
+        Err(ZumaReadError::FrameChanged)
     }
 
-    pub fn update_frog(&mut self) {
-        let mem_stream_parent =
-            DataMember::new_offset(self.process_handle.unwrap(), STREAM_PARENT_OFFSETS.to_vec())
-                .read();
+    // Make the next reads see the current state of the game
+    fn clear_cache(&self) {
+        if let Some(memory) = &self.memory {
+            memory.clear();
+        }
+    }
 
-        let mem_stream_parent: Game = match mem_stream_parent {
-            Ok(thing) => thing,
-            _ => return,
+    // The level being played, read through the first stream since they all share it
+    fn read_level(
+        &mut self,
+        game: &Game,
+        frog: Option<&Frog>,
+    ) -> Result<Option<Arc<Level>>, ZumaReadError> {
+        let stream_count = stream_count(game);
+        if stream_count == 0 {
+            return Ok(None);
+        }
+        let mem_stream: MemBallStream =
+            self.read("ball stream", vec![game.ballstream_ptrs[0] as usize])?;
+        let lvl_data: MemLvlData =
+            self.read("level data", vec![mem_stream.ptr_level_data as usize])?;
+
+        // Read the curve files, the second stream of a level follows the second curve
+        self.curves.resize_with(stream_count, Curve::new);
+        for i in 0..stream_count {
+            let ptr_filepath_curv = if i == 0 {
+                lvl_data.ptr_filepath_curv1
+            } else {
+                lvl_data.ptr_filepath_curv2
+            };
+            let curve_name = self.read_path("curve path", ptr_filepath_curv as usize)?;
+
+            let game_dir = self
+                .game_dir
+                .as_ref()
+                .map_err(|err| ZumaReadError::CurveMissing(format!("{} ({})", curve_name, err)))?;
+            let path = game_dir.join(curve_name + ".dat");
+            self.curves[i]
+                .read_from_file(path.to_string_lossy().into_owned())
+                .map_err(|err| {
+                    ZumaReadError::CurveMissing(format!("{} ({})", path.display(), err))
+                })?;
+        }
+
+        // The level only gives where the frog starts, where else it goes is learned by watching it
+        let id = c_string(&lvl_data.str_level_name);
+        let frog_position = Point {
+            x: lvl_data.frogx as f32,
+            y: lvl_data.frogy as f32,
         };
+        if self.frog_positions_level != id {
+            self.frog_positions = vec![frog_position];
+            self.frog_positions_level = id.clone();
+        }
+        if let Some(frog) = frog.filter(|frog| !frog.is_moving()) {
+            if !self
+                .frog_positions
+                .iter()
+                .any(|position| position.dist(&frog.location) <= FROG_POSITION_TOLERANCE)
+            {
+                self.frog_positions.push(frog.location);
+            }
+        }
 
-        let mem_frog: MemFrog = DataMember::new_offset(
-            self.process_handle.unwrap(),
-            vec![mem_stream_parent.ptr_frog as usize],
-        )
-        .read()
-        .unwrap();
+        Ok(Some(Arc::new(Level {
+            id,
+            display_name: c_string(&lvl_data.str_level_dispname),
+            frog_position,
+            frog_type: FrogType::from_positions(&self.frog_positions),
+            // Not knowing the background is not worth discarding the level
+            background: self
+                .read_path("background path", lvl_data.ptr_bg_img_path as usize)
+                .unwrap_or_default(),
+            curves: self.curves.clone(),
+        })))
+    }
 
-        let active_ball_maybe = self.read_ball(vec![mem_frog.ptr_active_ball as usize]);
-        let next_ball_maybe = self.read_ball(vec![mem_frog.ptr_next_ball as usize]);
-        if let (Some(active_ball), Some(next_ball)) = (active_ball_maybe, next_ball_maybe) {
-            self.frog = Some(Frog {
-                location: Point {
-                    x: mem_frog.target_x as f32,
-                    y: mem_frog.target_y as f32,
-                },
-                active_ball,
-                next_ball,
-                ball_exit_speed: mem_frog.ball_exit_speed,
-            })
+    // A path as the game stores it: windows style, relative to its own directory
+    fn read_path(&self, what: &'static str, address: usize) -> Result<String, ZumaReadError> {
+        let path: [u8; 50] = self.read(what, vec![address])?;
+        if !path.contains(&0) {
+            return Err(ZumaReadError::BadPointer(what, address));
+        }
+
+        Ok(c_string(&path).replace('\\', "/"))
+    }
+
+    fn read_streams(
+        &self,
+        game: &Game,
+        curves: &[Curve],
+    ) -> Result<Vec<BallStream>, ZumaReadError> {
+        let mut streams = vec![];
+        for (i, curve) in curves.iter().enumerate() {
+            let mem_stream: MemBallStream =
+                self.read("ball stream", vec![game.ballstream_ptrs[i] as usize])?;
+
+            let mut balls = self.read_balls(&mem_stream)?;
+
+            // Balls in tunnels can't be shot at
+            if curve.points.len() >= 2 {
+                for ball in &mut balls {
+                    ball.is_reachable = !curve.get_tunnel_at_dist(ball.distance_along_path);
+                }
+            }
+
+            streams.push(BallStream {
+                balls,
+                curve: curve.clone(),
+                forward_speed: mem_stream.balls_speed,
+                backwards_time_left: mem_stream.reverse_cooldown,
+                slowed_time_left: mem_stream.slowed_cooldown,
+                stopped_time_left: mem_stream.stopped_cooldown,
+                ..BallStream::new()
+            });
+        }
+
+        Ok(streams)
+    }
+
+    fn read_balls(&self, mem_stream: &MemBallStream) -> Result<Vec<Ball>, ZumaReadError> {
+        // Get the linked list manager thingymajig
+        let ball_linked_list: MemBallLinkedList =
+            self.read("ball list", vec![mem_stream.ptr_ball_linked_list as usize])?;
+
+        // Get the balls!
+        let mut balls = vec![];
+        let mut next_mem_ball = ball_linked_list.ptr_first_elem;
+        for _i in 0..mem_stream.ball_count {
+            let elem: MemBallLinkedListElement =
+                self.read("ball list element", vec![next_mem_ball as usize])?;
+            next_mem_ball = elem.ptr_next_elem;
+
+            balls.push(self.read_ball(elem.ptr_ball as usize)?);
         }
+
+        Ok(balls)
     }
 
-    pub fn update_mouse_coords(&mut self) {
-        self.mouse_coords = self.get_mouse_coords();
+    fn read_frog(&self, game: &Game) -> Result<Frog, ZumaReadError> {
+        let mem_frog: MemFrog = self.read("frog", vec![game.ptr_frog as usize])?;
+
+        let destination = Point {
+            x: mem_frog.target_x as f32,
+            y: mem_frog.target_y as f32,
+        };
+        let drawn = Point {
+            x: mem_frog.anim_x as f32,
+            y: mem_frog.anim_y as f32,
+        };
+        // The frog is drawn away from where it stands while it recoils from a shot,
+        // which is not moving
+        let recoil = Point {
+            x: mem_frog.recoil_max_x as f32,
+            y: mem_frog.recoil_max_y as f32,
+        };
+        let recoiling = drawn.dist(&destination) <= recoil.dot(&recoil).sqrt() + 1.;
+
+        Ok(Frog {
+            location: if recoiling { destination } else { drawn },
+            destination,
+            active_ball: self.read_ball(mem_frog.ptr_active_ball as usize)?,
+            next_ball: self.read_ball(mem_frog.ptr_next_ball as usize)?,
+            ball_exit_speed: mem_frog.ball_exit_speed,
+        })
+    }
+
+    pub fn update_mouse_coords(&mut self) -> Result<(), ZumaReadError> {
+        self.mouse_coords = None;
+        let [x, y] = self.read(
+            "mouse coordinates",
+            from_root(self.roots.mouse_coords, &MOUSE_COORDS_OFFSETS),
+        )?;
+        self.mouse_coords = Some((x, y));
+        Ok(())
     }
 }
 
-fn number_to_color(num: u32) -> Result<BallColor, String> {
+// Directory the game is installed in, the one that contains the levels.
+// Tried in order: the environment variable, the config file, the directory
+// the game was started from, and the default Steam location in its wine prefix.
+// Without a running game, e.g. for a dump, only the default wine prefix is known
+fn find_game_dir(pid: Option<Pid>) -> Result<PathBuf, String> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".config")));
+    let proc_dir = pid.map(|pid| PathBuf::from(format!("/proc/{}", pid)));
+
+    // The game may run in another prefix than the default one, e.g. with proton
+    let wine_prefix = proc_dir
+        .as_ref()
+        .and_then(|dir| std::fs::read(dir.join("environ")).ok())
+        .and_then(|environ| {
+            environ
+                .split(|&c| c == 0)
+                .find_map(|var| var.strip_prefix(b"WINEPREFIX="))
+                .map(|prefix| PathBuf::from(String::from_utf8_lossy(prefix).into_owned()))
+        })
+        .or_else(|| home.map(|home| home.join(".wine")));
+
+    let candidates = [
+        std::env::var_os(GAME_DIR_ENV).map(PathBuf::from),
+        config_dir
+            .and_then(|dir| std::fs::read_to_string(dir.join(GAME_DIR_CONFIG)).ok())
+            .map(|content| PathBuf::from(content.trim())),
+        proc_dir.and_then(|dir| std::fs::read_link(dir.join("cwd")).ok()),
+        wine_prefix.map(|prefix| prefix.join(STEAM_GAME_DIR)),
+    ];
+
+    let candidates: Vec<_> = candidates.into_iter().flatten().collect();
+    candidates
+        .iter()
+        .find(|dir| is_game_dir(dir))
+        .cloned()
+        .ok_or_else(|| {
+            let tried: Vec<_> = candidates
+                .iter()
+                .map(|dir| dir.display().to_string())
+                .collect();
+            format!(
+                "game files not found in {}, set {} to the game directory",
+                tried.join(", "),
+                GAME_DIR_ENV
+            )
+        })
+}
+
+fn is_game_dir(dir: &Path) -> bool {
+    dir.join("levels").is_dir()
+}
+
+// Streams of the game that can be read
+fn stream_count(game: &Game) -> usize {
+    (game.ballstream_count as usize).min(game.ballstream_ptrs.len())
+}
+
+// Replace the first address of a chain of offsets
+fn from_root(root: usize, offsets: &[usize]) -> Vec<usize> {
+    let mut offsets = offsets.to_vec();
+    offsets[0] = root;
+    offsets
+}
+
+// The text up to the first 0 byte
+fn c_string(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+fn mem_ball_to_ball(mem_ball: &MemBall) -> Result<Ball, ZumaReadError> {
+    Ok(Ball {
+        color: number_to_color(mem_ball.color)?,
+        coordinates: Point {
+            x: mem_ball.x,
+            y: mem_ball.y,
+        },
+        effect: number_to_effect(mem_ball.effect)?,
+        is_reachable: true,
+        distance_along_path: mem_ball.distance_along_path,
+        id: mem_ball.global_ball_number,
+    })
+}
+
+fn number_to_color(num: u32) -> Result<BallColor, ZumaReadError> {
     match num {
         0 => Ok(BallColor::Blue),
         1 => Ok(BallColor::Yellow),
@@ -319,61 +807,247 @@ fn number_to_color(num: u32) -> Result<BallColor, String> {
         3 => Ok(BallColor::Green),
         4 => Ok(BallColor::Purple),
         5 => Ok(BallColor::White),
-        _ => Err(format!("color read: 0x{:X}", num)),
+        _ => Err(ZumaReadError::InvalidColor(num)),
     }
 }
 
-fn number_to_effect(num: u32) -> Result<BallEffect, String> {
+fn number_to_effect(num: u32) -> Result<BallEffect, ZumaReadError> {
     match num {
         0 => Ok(BallEffect::Bomb),
         1 => Ok(BallEffect::Slow),
         2 => Ok(BallEffect::Visor),
         3 => Ok(BallEffect::Reverse),
         4 => Ok(BallEffect::None),
-        _ => Err(format!("effect read: 0x{:X}", num)),
+        _ => Err(ZumaReadError::InvalidEffect(num)),
     }
 }
 
-struct ScannableProc {
-    handle: Option<ProcessHandle>,
+struct ScannableProc<'a> {
+    memory: Option<&'a dyn MemorySource>,
     reader_cursor: usize,
 }
 
-impl Read for ScannableProc {
+impl Read for ScannableProc<'_> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let proc = if let Some(handle) = self.handle {
-            handle
-        } else {
-            return Err(Error::new(
-                ErrorKind::Other,
-                "Not connected to ZUMA process",
-            ));
+        const SCAN_END: usize = 0xA000000;
+        let Some(memory) = self.memory else {
+            return Err(std::io::Error::other("Not connected to ZUMA process"));
         };
 
-        const READ_STEP: usize = 32;
-        let remainder = buf.len() % READ_STEP;
+        let len = buf.len().min(SCAN_END.saturating_sub(self.reader_cursor));
+        let buf = &mut buf[..len];
 
-        // If it doesn't work, go byte by byte
-        for idx in (0..(buf.len() - remainder)).step_by(READ_STEP) {
-            if self.reader_cursor >= 0xA000000 {
-                return Ok(idx);
+        // If it doesn't work, some of it isn't mapped, so go page by page and leave those zeroed
+        if memory.read_uncached(self.reader_cursor, buf).is_none() {
+            let mut idx = 0;
+            while idx < len {
+                let address = self.reader_cursor + idx;
+                let page = &mut buf[idx..len.min(idx + PAGE_SIZE - address % PAGE_SIZE)];
+                if memory.read_uncached(address, page).is_none() {
+                    page.fill(0);
+                }
+                idx += page.len();
             }
-
-            let data = DataMember::<[u8; READ_STEP]>::new_offset(proc, vec![self.reader_cursor]);
-            buf[idx..idx + READ_STEP].copy_from_slice(&data.read().unwrap_or([0; READ_STEP]));
-            self.reader_cursor += READ_STEP;
         }
+        self.reader_cursor += len;
 
-        for idx in 0..remainder {
-            if self.reader_cursor >= 0xA000000 {
-                return Ok(buf.len() - remainder + idx);
-            }
+        Ok(len)
+    }
+}
 
-            let data = DataMember::new_offset(proc, vec![self.reader_cursor]);
-            buf[idx] = data.read().unwrap_or(0);
-            self.reader_cursor += 1;
-        }
+#[cfg(test)]
+fn put(mem: &mut [u8], offset: usize, bytes: [u8; 4]) {
+    mem[offset..offset + 4].copy_from_slice(&bytes);
+}
+
+// The offsets the game uses, written in a dump by hand rather than through the structs
+#[cfg(test)]
+fn layout_dump() -> DumpMemory {
+    layout_dump_with(STREAM_PARENT_OFFSETS[0], &[])
+}
 
-        Ok(buf.len())
+// The same, with the pointer to the game at another global and the given start of the image
+#[cfg(test)]
+fn layout_dump_with(stream_parent: usize, image: &[u8]) -> DumpMemory {
+    const HEAP: usize = 0x10000000;
+    let mut heap = vec![0; 4 * PAGE_SIZE];
+    let mut put_ball = |at: usize, id: u32, color: u32, effect: u32, dist: f32, x: f32, y: f32| {
+        put(&mut heap, at + 0x4, id.to_le_bytes());
+        put(&mut heap, at + 0x8, color.to_le_bytes());
+        put(&mut heap, at + 0xC, dist.to_le_bytes());
+        put(&mut heap, at + 0x1C, x.to_le_bytes());
+        put(&mut heap, at + 0x20, y.to_le_bytes());
+        put(&mut heap, at + 0x64, effect.to_le_bytes());
+    };
+    put_ball(0x1400, 1, 2, 4, 100., 10., 20.);
+    put_ball(0x1500, 2, 5, 3, 132., 40., 20.);
+    put_ball(0x2100, 3, 0, 4, 0., 0., 0.);
+    put_ball(0x2200, 4, 1, 0, 0., 0., 0.);
+    put_ball(0x3100, 5, 3, 4, 0., 300., 200.);
+    put_ball(0x3200, 6, 1, 4, 0., 100., 200.);
+
+    let pointers = [
+        // Game
+        (0x8C, 0x1000),
+        (0xB4, 0x3000),
+        (0x298, 0x2000),
+        // Stream, its list and the elements of the list
+        (0x1010, 0x3800),
+        (0x1030, 0x1100),
+        (0x1100, 0x1200),
+        (0x1200, 0x1210),
+        (0x1208, 0x1400),
+        (0x1214, 0x1200),
+        (0x1218, 0x1500),
+        // Frog
+        (0x202C, 0x2100),
+        (0x2030, 0x2200),
+        // Bullets, after the head of the list
+        (0x3000, 0x3010),
+        (0x3010, 0x3020),
+        (0x3018, 0x3100),
+        (0x3020, 0x3000),
+        (0x3028, 0x3200), // Doesn't move
+    ];
+    for (at, to) in pointers {
+        put(&mut heap, at, ((HEAP + to) as u32).to_le_bytes());
+    }
+    for (at, value) in [
+        (0xB8, 2u32),
+        (0xBC, 1),
+        (0xF4, 1234),
+        (0x1034, 2),
+        (0x1058, 7),
+    ] {
+        put(&mut heap, at, value.to_le_bytes());
     }
+    for (at, value) in [(0x1064, 0.5f32), (0x2044, 8.), (0x3170, 3.), (0x3174, 4.)] {
+        put(&mut heap, at, value.to_le_bytes());
+    }
+    // A frog standing still is drawn where it stands
+    for at in [0x2008, 0x2010] {
+        put(&mut heap, at, 320u32.to_le_bytes());
+        put(&mut heap, at + 4, 240u32.to_le_bytes());
+    }
+
+    // Level data
+    heap[0x3808..0x380E].copy_from_slice(b"spiral");
+    heap[0x3824..0x382A].copy_from_slice(b"Spiral");
+    put(&mut heap, 0x38B0, ((HEAP + 0x3A00) as u32).to_le_bytes());
+    heap[0x3A00..0x3A14].copy_from_slice(b"levels\\spiral\\spiral");
+    put(&mut heap, 0x3860, 300u32.to_le_bytes());
+    put(&mut heap, 0x3864, 250u32.to_le_bytes());
+
+    // The page with the pointer to the game
+    const GLOBALS: usize = 0x83F000;
+    let mut globals = vec![0; PAGE_SIZE];
+    put(
+        &mut globals,
+        stream_parent - GLOBALS,
+        (HEAP as u32).to_le_bytes(),
+    );
+
+    let mut code = image.to_vec();
+    code.resize(PAGE_SIZE, 0);
+    let maps = "00400000-00401000 r-xp 00000000 00:00 0\n\
+                0083f000-00840000 rw-p 00000000 00:00 0\n\
+                10000000-10004000 rw-p 00000000 00:00 0\n";
+    DumpMemory::new(maps, [code, globals, heap].concat()).unwrap()
+}
+
+#[test]
+fn struct_layouts() {
+    let reader = ZumaReader {
+        memory: Some(Box::new(layout_dump())),
+        ..ZumaReader::new()
+    };
+
+    let game = reader.read_game().unwrap();
+    assert_eq!(1234, game.igt);
+    assert_eq!(0, game.game_state);
+
+    let stream: MemBallStream = reader
+        .read("ball stream", vec![game.ballstream_ptrs[0] as usize])
+        .unwrap();
+    assert_eq!(0.5, stream.balls_speed);
+    assert_eq!(7, stream.slowed_cooldown);
+    let lvl_data: MemLvlData = reader
+        .read("level data", vec![stream.ptr_level_data as usize])
+        .unwrap();
+    assert_eq!("spiral", c_string(&lvl_data.str_level_name));
+    assert_eq!("Spiral", c_string(&lvl_data.str_level_dispname));
+    assert_eq!((300, 250), (lvl_data.frogx, lvl_data.frogy));
+
+    let balls = reader.read_balls(&stream).unwrap();
+    assert_eq!(
+        vec![1, 2],
+        balls.iter().map(|ball| ball.id).collect::<Vec<_>>()
+    );
+    assert_eq!(BallColor::Red, balls[0].color);
+    assert_eq!(BallEffect::Reverse, balls[1].effect);
+    assert_eq!(132., balls[1].distance_along_path);
+    assert_eq!(Point { x: 10., y: 20. }, balls[0].coordinates);
+
+    let frog = reader.read_frog(&game).unwrap();
+    assert_eq!(Point { x: 320., y: 240. }, frog.location);
+    assert!(!frog.is_moving());
+    assert_eq!(BallColor::Blue, frog.active_ball.color);
+    assert_eq!(BallEffect::Bomb, frog.next_ball.effect);
+    assert_eq!(8., frog.ball_exit_speed);
+
+    // The ball that doesn't move is left out
+    let projectiles = reader.read_projectiles(&game).unwrap();
+    assert_eq!(1, projectiles.len());
+    assert_eq!(BallColor::Green, projectiles[0].ball.color);
+    assert_eq!(5., projectiles[0].speed);
+}
+
+#[test]
+fn snapshot_without_curves() {
+    let mut reader = ZumaReader {
+        memory: Some(Box::new(layout_dump())),
+        game_dir: Err("not found".into()),
+        ..ZumaReader::new()
+    };
+
+    // The curve files can't be found, the balls are still read
+    let snapshot = reader.snapshot().unwrap();
+    assert!(snapshot.game_state.level.is_none());
+    assert!(matches!(
+        snapshot.level_error,
+        Some(ZumaReadError::CurveMissing(ref curve)) if curve.starts_with("levels/spiral/spiral")
+    ));
+    assert_eq!(1, snapshot.game_state.streams.len());
+    assert_eq!(2, snapshot.game_state.streams[0].balls.len());
+    assert!(snapshot.frog.is_some());
+}
+
+#[test]
+fn roots_are_found_by_signature() {
+    // Another build, which keeps the game elsewhere and loads it with the same code
+    let moved = 0x83F100;
+    let mut code = vec![0x90; 0x40];
+    code.extend([0x8B, 0x0D]);
+    code.extend((moved as u32).to_le_bytes());
+    code.extend([
+        0x8B, 0x01, 0x85, 0xC0, 0x74, 0x10, 0x8B, 0x88, 0x8C, 0x00, 0x00, 0x00,
+    ]);
+
+    let mut reader = ZumaReader {
+        memory: Some(Box::new(layout_dump_with(moved, &code))),
+        ..ZumaReader::new()
+    };
+    reader.roots = reader.scan_roots();
+    assert_eq!(moved, reader.roots.stream_parent);
+    assert_eq!(vec!["game"], reader.roots.scanned);
+    assert_eq!(1234, reader.read_game().unwrap().igt);
+
+    // The constant is kept when it leads to a game, the signature isn't needed
+    let mut reader = ZumaReader {
+        memory: Some(Box::new(layout_dump_with(STREAM_PARENT_OFFSETS[0], &code))),
+        ..ZumaReader::new()
+    };
+    reader.roots = reader.scan_roots();
+    assert_eq!(STREAM_PARENT_OFFSETS[0], reader.roots.stream_parent);
+    assert!(reader.roots.scanned.is_empty());
 }
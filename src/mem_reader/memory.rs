@@ -0,0 +1,316 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use sysinfo::Pid;
+
+pub const PAGE_SIZE: usize = 0x1000;
+const POINTER_SIZE: usize = 4; // The game is a 32 bit program
+const ADDRESS_SPACE_END: u64 = 1 << 32; // Nothing of the game is mapped above
+
+// Types that can be read from raw memory. Implementing it is only sound for types
+// that have a defined layout and for which any bytes are a valid value
+#[allow(clippy::missing_safety_doc)]
+pub unsafe trait Plain: Copy {}
+
+unsafe impl Plain for u8 {}
+unsafe impl Plain for u32 {}
+unsafe impl Plain for f32 {}
+unsafe impl<T: Plain, const N: usize> Plain for [T; N] {}
+
+// Somewhere the memory of the game can be read from: the running game or a dump of it
+pub trait MemorySource: std::fmt::Debug {
+    // Fill `buf` with the memory at `address`, failing if any of it can't be read
+    fn read_uncached(&self, address: usize, buf: &mut [u8]) -> Option<()>;
+
+    // Same, but what was read since the last `clear` may be reused
+    fn read_bytes(&self, address: usize, buf: &mut [u8]) -> Option<()> {
+        self.read_uncached(address, buf)
+    }
+
+    // Forget what was cached, e.g. to read a new frame
+    fn clear(&self) {}
+
+    // False once nothing can be read anymore, e.g. because the game exited
+    fn is_alive(&self) -> bool {
+        true
+    }
+}
+
+impl dyn MemorySource + '_ {
+    pub fn read<T: Plain>(&self, address: usize) -> Option<T> {
+        let mut buf = vec![0; std::mem::size_of::<T>()];
+        self.read_bytes(address, &mut buf)?;
+        // Safety: the buffer has the size of T, and any bytes are a valid T
+        Some(unsafe { std::ptr::read_unaligned(buf.as_ptr() as *const T) })
+    }
+
+    // Follow a chain of pointers: every offset after the first one is added
+    // to the pointer found at the previous address
+    pub fn read_chain<T: Plain>(&self, offsets: &[usize]) -> Option<T> {
+        let (&first, rest) = offsets.split_first()?;
+        let mut address = first;
+        for &offset in rest {
+            let mut pointer = [0; POINTER_SIZE];
+            self.read_bytes(address, &mut pointer)?;
+            address = (u32::from_le_bytes(pointer) as usize).checked_add(offset)?;
+        }
+
+        self.read(address)
+    }
+}
+
+// Memory of the game read through /proc/pid/mem, whole pages at a time.
+// The pages are kept until `clear` so that reading the balls, which are
+// close to each other, only goes to the kernel once per page
+#[derive(Debug)]
+pub struct ProcMemory {
+    pub pid: Pid,
+    file: File,
+    pages: RefCell<HashMap<usize, Option<Box<[u8]>>>>, // None for pages that can't be read
+}
+
+impl ProcMemory {
+    pub fn open(pid: Pid) -> std::io::Result<Self> {
+        Ok(Self {
+            pid,
+            file: File::open(format!("/proc/{}/mem", pid))?,
+            pages: RefCell::new(HashMap::new()),
+        })
+    }
+
+    // Save every readable region of the game, to read it later with `DumpMemory`
+    pub fn dump(&self, dir: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        let maps = std::fs::read_to_string(format!("/proc/{}/maps", self.pid))?;
+        let mut dumped_maps = String::new();
+        let mut mem = std::io::BufWriter::new(File::create(dir.join("mem"))?);
+
+        for line in maps.lines() {
+            let Some((range, readable)) = parse_maps_line(line) else {
+                continue;
+            };
+            if !readable || range.end as u64 > ADDRESS_SPACE_END {
+                continue;
+            }
+
+            // Some mappings can't be read even though they say so, those pages are left zeroed
+            let mut page = [0; PAGE_SIZE];
+            for address in range.step_by(PAGE_SIZE) {
+                if self.read_uncached(address, &mut page).is_none() {
+                    page.fill(0);
+                }
+                mem.write_all(&page)?;
+            }
+            dumped_maps += line;
+            dumped_maps += "\n";
+        }
+
+        mem.flush()?;
+        std::fs::write(dir.join("maps"), dumped_maps)
+    }
+}
+
+impl MemorySource for ProcMemory {
+    fn read_uncached(&self, address: usize, buf: &mut [u8]) -> Option<()> {
+        self.file.read_exact_at(buf, address as u64).ok()
+    }
+
+    fn read_bytes(&self, address: usize, buf: &mut [u8]) -> Option<()> {
+        let mut pages = self.pages.borrow_mut();
+        let mut done = 0;
+        while done < buf.len() {
+            let start = address.checked_add(done)?;
+            let page_address = start - start % PAGE_SIZE;
+            let page = pages
+                .entry(page_address)
+                .or_insert_with(|| {
+                    let mut page = vec![0; PAGE_SIZE].into_boxed_slice();
+                    self.read_uncached(page_address, &mut page)?;
+                    Some(page)
+                })
+                .as_ref()?;
+
+            let in_page = start - page_address;
+            let len = (PAGE_SIZE - in_page).min(buf.len() - done);
+            buf[done..done + len].copy_from_slice(&page[in_page..in_page + len]);
+            done += len;
+        }
+
+        Some(())
+    }
+
+    fn clear(&self) {
+        self.pages.borrow_mut().clear();
+    }
+
+    fn is_alive(&self) -> bool {
+        Path::new(&format!("/proc/{}", self.pid)).exists()
+    }
+}
+
+// Memory of the game saved by `ProcMemory::dump`: a `maps` file listing the
+// regions like /proc/pid/maps does, and a `mem` file with their content back to back
+#[derive(Debug)]
+pub struct DumpMemory {
+    regions: Vec<(Range<usize>, usize)>, // Addresses, and where they start in `mem`
+    mem: Vec<u8>,
+}
+
+impl DumpMemory {
+    pub fn load(dir: &Path) -> std::io::Result<Self> {
+        let maps = std::fs::read_to_string(dir.join("maps"))?;
+        Self::new(&maps, std::fs::read(dir.join("mem"))?)
+    }
+
+    pub fn new(maps: &str, mem: Vec<u8>) -> std::io::Result<Self> {
+        let mut regions = vec![];
+        let mut offset = 0;
+        for line in maps.lines().filter(|line| !line.trim().is_empty()) {
+            let (range, _) = parse_maps_line(line).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid region {:?}", line),
+                )
+            })?;
+            let len = range.len();
+            regions.push((range, offset));
+            offset += len;
+        }
+
+        if offset != mem.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Regions add up to {} bytes but got {}", offset, mem.len()),
+            ));
+        }
+
+        Ok(Self { regions, mem })
+    }
+}
+
+impl MemorySource for DumpMemory {
+    fn read_uncached(&self, address: usize, buf: &mut [u8]) -> Option<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            let start = address.checked_add(done)?;
+            let (range, offset) = self
+                .regions
+                .iter()
+                .find(|(range, _)| range.contains(&start))?;
+
+            let in_region = start - range.start;
+            let len = (range.len() - in_region).min(buf.len() - done);
+            let from = offset + in_region;
+            buf[done..done + len].copy_from_slice(&self.mem[from..from + len]);
+            done += len;
+        }
+
+        Some(())
+    }
+}
+
+// The addresses of a line of /proc/pid/maps, and whether they can be read
+fn parse_maps_line(line: &str) -> Option<(Range<usize>, bool)> {
+    let mut fields = line.split_whitespace();
+    let (start, end) = fields.next()?.split_once('-')?;
+    let readable = fields.next()?.starts_with('r');
+    let start = usize::from_str_radix(start, 16).ok()?;
+    let end = usize::from_str_radix(end, 16).ok()?;
+
+    (start <= end).then_some((start..end, readable))
+}
+
+#[test]
+fn reads_own_memory() {
+    let memory = ProcMemory::open(std::process::id() as Pid).unwrap();
+    let memory: &dyn MemorySource = &memory;
+    // Values that straddle two pages are put together from both
+    let values: Vec<u32> = (0..2 * PAGE_SIZE as u32).collect();
+    let address = values.as_ptr() as usize;
+    let next_page = (address / PAGE_SIZE + 1) * PAGE_SIZE;
+    let idx = (next_page - address) / 4 - 1;
+
+    assert_eq!(Some(values[7]), memory.read::<u32>(address + 7 * 4));
+    assert_eq!(
+        Some([values[idx], values[idx + 1]]),
+        memory.read::<[u32; 2]>(address + idx * 4)
+    );
+    assert_eq!(None, memory.read::<u32>(0));
+}
+
+#[test]
+fn dump_regions() {
+    let maps = "00001000-00002000 rw-p 00000000 00:00 0\n\
+                00002000-00003000 r--p 00000000 00:00 0 /game.exe\n\
+                00010000-00011000 rw-p 00000000 00:00 0 [heap]\n";
+    let mut mem = vec![0; 3 * PAGE_SIZE];
+    mem[0xFFE..0x1002].copy_from_slice(&[1, 2, 3, 4]);
+    mem[0x2000..0x2004].copy_from_slice(&0x1FFEu32.to_le_bytes());
+    let dump = DumpMemory::new(maps, mem).unwrap();
+    let dump: &dyn MemorySource = &dump;
+
+    // Reads can span neighbouring regions, and pointers lead to other regions
+    assert_eq!(Some(0x04030201), dump.read::<u32>(0x1FFE));
+    assert_eq!(Some(0x04030201), dump.read_chain::<u32>(&[0x10000, 0]));
+    assert_eq!(None, dump.read::<u32>(0x3FFE));
+    assert_eq!(None, dump.read::<u8>(0x5000));
+
+    assert!(DumpMemory::new(maps, vec![0; PAGE_SIZE]).is_err());
+}
+
+// How much the page cache saves over reading every field on its own, as the reader used to.
+// It measures time, so it only runs when asked: cargo test --release -- --ignored --nocapture
+#[test]
+#[ignore]
+fn page_cache_speed() {
+    // Balls and the elements of their list, with the fields the reader reads from each
+    const BALLS: usize = 500;
+    const BALL_FIELDS: [usize; 6] = [0x4, 0x8, 0xC, 0x1C, 0x20, 0x64];
+    const ELEMENT_FIELDS: [usize; 3] = [0x0, 0x4, 0x8];
+    let balls = vec![[7u8; 0x70]; BALLS];
+    let elements = vec![[9u8; 0xC]; BALLS];
+    let fields: Vec<usize> = (0..BALLS)
+        .flat_map(|i| {
+            let element = elements[i].as_ptr() as usize;
+            let ball = balls[i].as_ptr() as usize;
+            let element_fields = ELEMENT_FIELDS.iter().map(move |field| element + field);
+            element_fields.chain(BALL_FIELDS.iter().map(move |field| ball + field))
+        })
+        .collect();
+
+    let memory = ProcMemory::open(std::process::id() as Pid).unwrap();
+    let read_all = |read: &dyn Fn(usize, &mut [u8]) -> Option<()>| {
+        let start = std::time::Instant::now();
+        let mut sum = 0;
+        for &field in &fields {
+            let mut value = [0; 4];
+            read(field, &mut value).unwrap();
+            sum = u32::from_le_bytes(value).wrapping_add(sum);
+        }
+        (start.elapsed(), sum)
+    };
+
+    const RUNS: u32 = 20;
+    let (mut per_field, mut cached) = (std::time::Duration::ZERO, std::time::Duration::ZERO);
+    for _ in 0..RUNS {
+        let (time, per_field_sum) = read_all(&|address, buf| memory.read_uncached(address, buf));
+        per_field += time;
+        memory.clear();
+        let (time, cached_sum) = read_all(&|address, buf| memory.read_bytes(address, buf));
+        cached += time;
+        assert_eq!(per_field_sum, cached_sum);
+    }
+    println!(
+        "{} fields of {} balls: {:?} one read per field, {:?} through the page cache ({:.0}x)",
+        fields.len(),
+        BALLS,
+        per_field / RUNS,
+        cached / RUNS,
+        per_field.as_secs_f64() / cached.as_secs_f64()
+    );
+}
@@ -0,0 +1,76 @@
+use std::io::Read;
+
+// Where the game executable is mapped, its code and globals are all in there
+pub const IMAGE_START: usize = 0x400000;
+pub const IMAGE_END: usize = 0x900000;
+
+// Bytes of code around an instruction that uses a global, which stay the same across builds
+// even when the address of the global moves
+#[derive(Clone, Copy, Debug)]
+pub struct Signature {
+    pub pattern: &'static str, // Hex bytes separated by spaces, ?? matches any byte
+    pub operand: usize,        // Where the absolute address of the global is in the pattern
+}
+
+impl Signature {
+    fn bytes(&self) -> Vec<Option<u8>> {
+        self.pattern
+            .split_whitespace()
+            .map(|byte| u8::from_str_radix(byte, 16).ok())
+            .collect()
+    }
+
+    // Addresses of the globals used by every match in the image, in order,
+    // which starts at `base`. Anything pointing outside of the image is not a global
+    pub fn find_all<'a>(&self, image: &'a [u8], base: usize) -> impl Iterator<Item = usize> + 'a {
+        let pattern = self.bytes();
+        let operand = self.operand;
+        image
+            .windows(pattern.len())
+            .enumerate()
+            .filter(move |(_, window)| {
+                window
+                    .iter()
+                    .zip(&pattern)
+                    .all(|(byte, expected)| expected.is_none_or(|expected| expected == *byte))
+            })
+            .filter_map(move |(start, _)| {
+                let operand = image.get(start + operand..start + operand + 4)?;
+                let address = u32::from_le_bytes(operand.try_into().unwrap()) as usize;
+                (base..base + image.len())
+                    .contains(&address)
+                    .then_some(address)
+            })
+    }
+}
+
+// Read the whole executable image, to search it for signatures
+pub fn read_image<R: Read>(reader: R) -> std::io::Result<Vec<u8>> {
+    let mut image = Vec::with_capacity(IMAGE_END - IMAGE_START);
+    reader
+        .take((IMAGE_END - IMAGE_START) as u64)
+        .read_to_end(&mut image)?;
+    Ok(image)
+}
+
+#[test]
+fn signature_finds_global() {
+    let signature = Signature {
+        pattern: "A1 ?? ?? ?? ?? 8B 88 20 03 00 00",
+        operand: 1,
+    };
+    let mut image = vec![0x90; 0x100];
+    // Same instruction but with another offset, which must not match
+    image[0x10..0x1B].copy_from_slice(&[0xA1, 0x00, 0x02, 0x40, 0x00, 0x8B, 0x88, 0x24, 3, 0, 0]);
+    image[0x40..0x4B].copy_from_slice(&[0xA1, 0x80, 0x00, 0x40, 0x00, 0x8B, 0x88, 0x20, 3, 0, 0]);
+    let find = |image: &[u8]| signature.find_all(image, IMAGE_START).collect::<Vec<_>>();
+    assert_eq!(vec![0x400080], find(&image));
+
+    // Every match is found, so that the next one can be tried when one doesn't lead anywhere
+    image[0x80..0x8B].copy_from_slice(&[0xA1, 0xC0, 0x00, 0x40, 0x00, 0x8B, 0x88, 0x20, 3, 0, 0]);
+    assert_eq!(vec![0x400080, 0x4000C0], find(&image));
+
+    // A match that refers to something outside of the image is ignored
+    image[0x43] = 0x50;
+    assert_eq!(vec![0x4000C0], find(&image));
+}
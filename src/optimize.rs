@@ -0,0 +1,352 @@
+use crate::bot::{ParamSpec, Strategy, StrategyInfo};
+use crate::headless::{self, GameReport, GameResult, LevelConfig};
+use crate::libzuma::simulator::Rng;
+use std::fmt::Write;
+
+const USAGE: &str = "Usage: zum-ai-steam optimize <curve.dat>... --mode <name> [options]
+
+Tune the parameters of a bot by random search: every candidate set of parameters
+plays the same games on every level, and the one with the best mean score is kept.
+Every curve file is a level, curves joined with + make a level with a stream of balls
+on each of them.
+
+Options:
+    --candidates <n>       Parameter sets tried per bot, the starting one included (default: 50)
+    --out <path>           Profile written with the best parameters (default: bot-profile.txt)
+    --report <path>        Scores of the best parameters on every level (default: <out>.report)
+    --games <n>            Games per level and candidate (default: 20)
+    --profile <path>       Parameters to start from, kept in the profile written
+    Every other option of simulate, --mode being required";
+
+// What winning a game is worth, more than what anything else in a game can add up to
+const WIN_SCORE: f32 = 1000.;
+// What each shot costs. A won game clears every ball the frog shot as well, so it is
+// the shots that tell how well it was played
+const SHOT_COST: f32 = 0.5;
+// Size of the changes made to the best parameters, as a fraction of their range:
+// at first, at most and at least. It grows when a change helps, and shrinks otherwise
+const INITIAL_STEP: f32 = 0.2;
+const MAX_STEP: f32 = 0.5;
+const MIN_STEP: f32 = 0.02;
+// Chance for each parameter to be changed, on top of one that always is
+const CHANGE_CHANCE: f32 = 0.25;
+// The best parameters are also played on seeds this far after the ones used to tune them,
+// to see how much of the gain comes from fitting those games
+const NEW_SEEDS_OFFSET: u64 = 1_000_000;
+
+// What a game is worth: winning comes first, then clearing the chain with few shots,
+// which is what combos and chain reactions do
+fn score(report: &GameReport) -> f32 {
+    let won = if report.result == GameResult::Won {
+        WIN_SCORE
+    } else {
+        0.
+    };
+    // Balls of the chain cleared, without the ones the frog shot into it
+    let progress = report.balls_cleared as f32 - report.shots_fired as f32;
+
+    won + progress - SHOT_COST * report.shots_fired as f32
+}
+
+// Scores of a set of parameters, by level then by game
+struct Evaluation(Vec<Vec<f32>>);
+
+impl Evaluation {
+    fn mean(&self) -> f32 {
+        mean_variance(&self.0.concat()).0
+    }
+}
+
+fn mean_variance(scores: &[f32]) -> (f32, f32) {
+    let count = scores.len().max(1) as f32;
+    let mean = scores.iter().sum::<f32>() / count;
+    let variance = scores.iter().map(|s| (s - mean).powi(2)).sum::<f32>() / count;
+    (mean, variance)
+}
+
+// Every parameter of a strategy, in the order of its sets
+fn specs(strategy: &dyn Strategy) -> Vec<ParamSpec> {
+    strategy
+        .param_sets()
+        .iter()
+        .flat_map(|set| set.specs().iter().copied())
+        .collect()
+}
+
+fn values(strategy: &dyn Strategy) -> Vec<f32> {
+    strategy
+        .param_sets()
+        .iter()
+        .flat_map(|set| (0..set.specs().len()).map(|idx| set.get(idx)))
+        .collect()
+}
+
+fn set_values(strategy: &mut dyn Strategy, values: &[f32]) {
+    let mut values = values.iter();
+    for set in strategy.param_sets_mut() {
+        for (idx, &value) in (0..set.specs().len()).zip(&mut values) {
+            set.set(idx, value);
+        }
+    }
+}
+
+// Play the games of every level with the given parameters, spread over every core
+fn evaluate(
+    mode: StrategyInfo,
+    values: &[f32],
+    levels: &[LevelConfig],
+    games: u64,
+    seed: u64,
+) -> Evaluation {
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get() as u64);
+    let play = |level: &LevelConfig, game: u64| {
+        let mut strategy = (mode.make)();
+        set_values(strategy.as_mut(), values);
+        score(&headless::play_game(
+            level,
+            strategy.as_mut(),
+            seed.wrapping_add(game),
+        ))
+    };
+
+    let scores = levels
+        .iter()
+        .map(|level| {
+            let mut scores = vec![0.; games as usize];
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = (0..workers)
+                    .map(|worker| {
+                        scope.spawn(move || {
+                            (worker..games)
+                                .step_by(workers as usize)
+                                .map(|game| (game, play(level, game)))
+                                .collect::<Vec<_>>()
+                        })
+                    })
+                    .collect();
+                for handle in handles {
+                    for (game, score) in handle.join().unwrap() {
+                        scores[game as usize] = score;
+                    }
+                }
+            });
+            scores
+        })
+        .collect();
+
+    Evaluation(scores)
+}
+
+// Change a few of the parameters by a random amount, the others stay as they are
+fn neighbor(values: &[f32], specs: &[ParamSpec], step: f32, rng: &mut Rng) -> Vec<f32> {
+    let always = rng.below(values.len());
+    values
+        .iter()
+        .zip(specs)
+        .enumerate()
+        .map(|(idx, (&value, spec))| {
+            if idx != always && rng.unit() >= CHANGE_CHANCE {
+                return value;
+            }
+            // Normally distributed, from two uniform numbers
+            let normal = (-2. * (1. - rng.unit()).ln()).sqrt()
+                * (2. * std::f32::consts::PI * rng.unit()).cos();
+            let change = normal * step * (spec.max - spec.min);
+            // Whole parameters move by at least one
+            let value = match spec.integer {
+                true if change.abs() < 1. => value + change.signum(),
+                true => (value + change).round(),
+                false => value + change,
+            };
+            value.clamp(spec.min, spec.max)
+        })
+        .collect()
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut candidates = 50;
+    let mut out = "bot-profile.txt".to_string();
+    let mut report_path = None;
+    let options = headless::parse_options(args, USAGE, |arg, value| {
+        match arg {
+            "--candidates" => candidates = headless::parse(arg, value)?,
+            "--out" => out = value.to_string(),
+            "--report" => report_path = Some(value.to_string()),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    if options.modes.is_empty() {
+        return Err(format!("Missing --mode\n\n{}", USAGE));
+    }
+    let report_path = report_path.unwrap_or(format!("{}.report", out));
+    let games = options.games.unwrap_or(20);
+    let levels = options
+        .paths
+        .iter()
+        .map(|path| {
+            let curve_paths: Vec<_> = path.split('+').map(String::from).collect();
+            headless::load_level(&options.level, &curve_paths)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut profile = options.profile.clone();
+    let mut report = String::new();
+    for &mode in &options.modes {
+        let mut strategy = (mode.make)();
+        options.profile.apply(strategy.as_mut());
+        let specs = specs(strategy.as_ref());
+        let initial = values(strategy.as_ref());
+        if initial.is_empty() {
+            continue;
+        }
+
+        let initial_evaluation = evaluate(mode, &initial, &levels, games, options.seed);
+        println!("{}: starting at {:.1}", mode, initial_evaluation.mean());
+        let mut best = (initial.clone(), initial_evaluation.mean());
+        let mut best_evaluation = None;
+        let mut step = INITIAL_STEP;
+        let mut rng = Rng::derive(options.seed, "optimize", 0);
+        for candidate in 1..candidates {
+            let values = neighbor(&best.0, &specs, step, &mut rng);
+            let evaluation = evaluate(mode, &values, &levels, games, options.seed);
+            let mean = evaluation.mean();
+            let improved = mean > best.1;
+            if improved {
+                best = (values, mean);
+                best_evaluation = Some(evaluation);
+                step = (step * 1.5).min(MAX_STEP);
+            } else {
+                step = (step * 0.9).max(MIN_STEP);
+            }
+            if options.verbose || improved {
+                println!(
+                    "{}: candidate {}/{} scored {:.1}, best {:.1}",
+                    mode,
+                    candidate + 1,
+                    candidates,
+                    mean,
+                    best.1
+                );
+            }
+        }
+
+        set_values(strategy.as_mut(), &best.0);
+        profile.store(strategy.as_ref());
+        // Without any better candidate, the best games are the first ones
+        let best_evaluation = best_evaluation.as_ref().unwrap_or(&initial_evaluation);
+        let new_seeds = options.seed.wrapping_add(NEW_SEEDS_OFFSET);
+        let new_evaluation = evaluate(mode, &best.0, &levels, games, new_seeds);
+        write_report(
+            &mut report,
+            mode,
+            &levels,
+            [&initial_evaluation, best_evaluation, &new_evaluation],
+        );
+        writeln!(
+            report,
+            "    {:<32} {:>10} {:>10}",
+            "parameter", "before", "after"
+        )
+        .unwrap();
+        for ((spec, before), after) in specs.iter().zip(&initial).zip(&best.0) {
+            writeln!(
+                report,
+                "    {:<32} {:>10.3} {:>10.3}",
+                spec.name, before, after
+            )
+            .unwrap();
+        }
+        writeln!(report).unwrap();
+    }
+
+    profile
+        .save(&out)
+        .map_err(|err| format!("Could not write {}: {}", out, err))?;
+    writeln!(
+        report,
+        "Score of a game: {} if won, plus the balls of the chain cleared, less {} per shot.\n\
+         {} games per level on seeds {} to {}, and as many new ones from seed {}.",
+        WIN_SCORE,
+        SHOT_COST,
+        games,
+        options.seed,
+        options.seed.wrapping_add(games.saturating_sub(1)),
+        options.seed.wrapping_add(NEW_SEEDS_OFFSET)
+    )
+    .unwrap();
+    std::fs::write(&report_path, &report)
+        .map_err(|err| format!("Could not write {}: {}", report_path, err))?;
+    print!("\n{}", report);
+    println!("Wrote {} and {}", out, report_path);
+
+    Ok(())
+}
+
+// Mean and variance of the scores on every level, before and after tuning
+fn write_report(
+    report: &mut String,
+    mode: StrategyInfo,
+    levels: &[LevelConfig],
+    [before, after, new_seeds]: [&Evaluation; 3],
+) {
+    writeln!(report, "{} ({})", mode, mode.id).unwrap();
+    writeln!(
+        report,
+        "    {:<24} {:>20} {:>20} {:>20}",
+        "level (mean, variance)", "before", "after", "after, new seeds"
+    )
+    .unwrap();
+    let cell = |scores: &[f32]| {
+        let (mean, variance) = mean_variance(scores);
+        format!("{:.1}, {:.0}", mean, variance)
+    };
+    for (idx, level) in levels.iter().enumerate() {
+        writeln!(
+            report,
+            "    {:<24} {:>20} {:>20} {:>20}",
+            level.name,
+            cell(&before.0[idx]),
+            cell(&after.0[idx]),
+            cell(&new_seeds.0[idx])
+        )
+        .unwrap();
+    }
+    writeln!(
+        report,
+        "    {:<24} {:>20} {:>20} {:>20}",
+        "all",
+        cell(&before.0.concat()),
+        cell(&after.0.concat()),
+        cell(&new_seeds.0.concat())
+    )
+    .unwrap();
+}
+
+#[test]
+fn neighbors_stay_in_range() {
+    let strategy = (StrategyInfo::find("SearchPlanner").unwrap().make)();
+    let specs = specs(strategy.as_ref());
+    let initial = values(strategy.as_ref());
+    assert_eq!(specs.len(), initial.len());
+
+    let mut rng = Rng::new(3);
+    for _ in 0..100 {
+        let values = neighbor(&initial, &specs, MAX_STEP, &mut rng);
+        assert_ne!(initial, values);
+        for (value, spec) in values.iter().zip(&specs) {
+            assert!((spec.min..=spec.max).contains(value));
+            assert!(!spec.integer || value.fract() == 0.);
+        }
+
+        let mut changed = (StrategyInfo::find("SearchPlanner").unwrap().make)();
+        set_values(changed.as_mut(), &values);
+        assert_eq!(values, self::values(changed.as_ref()));
+    }
+}
+
+#[test]
+fn scores_are_summarized() {
+    assert_eq!((2., 1.), mean_variance(&[1., 3.]));
+    assert_eq!((0., 0.), mean_variance(&[]));
+}
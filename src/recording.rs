@@ -0,0 +1,673 @@
+use crate::bot::BotMove;
+use crate::libzuma::trajectory::Projectile;
+use crate::libzuma::*;
+use binrw::{binrw, BinRead, BinResult, BinWrite};
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Read, Seek, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+// A recording is a header followed by a flat list of records.
+// Curves are only written when they change, and the frog and move records
+// belong to the state record written right before them. Levels are written like curves,
+// and again whenever more is learned about how the frog moves
+const VERSION: u16 = 3;
+
+#[binrw]
+#[brw(little, magic = b"ZREC")]
+struct RecHeader {
+    #[br(assert(version == VERSION, "unsupported recording version {}", version))]
+    version: u16,
+}
+
+#[binrw]
+#[brw(little)]
+enum Record {
+    #[brw(magic = 0u8)]
+    Curve(RecCurve),
+    #[brw(magic = 1u8)]
+    State(RecState),
+    #[brw(magic = 2u8)]
+    Frog(RecFrog),
+    #[brw(magic = 3u8)]
+    Move(RecMove),
+    #[brw(magic = 4u8)]
+    Level(RecLevel),
+}
+
+#[binrw]
+#[derive(Clone)]
+struct RecCurve {
+    stream: u8,
+    #[br(temp)]
+    #[bw(calc = points.len() as u32)]
+    point_count: u32,
+    #[br(count = point_count)]
+    points: Vec<RecCurvePoint>,
+}
+
+#[binrw]
+#[derive(Clone, Copy)]
+struct RecCurvePoint {
+    point: Point,
+    is_tunnel: u8,
+}
+
+#[binrw]
+#[derive(Clone)]
+struct RecState {
+    time_ms: u32,
+    #[br(temp)]
+    #[bw(calc = streams.len() as u32)]
+    stream_count: u32,
+    #[br(count = stream_count)]
+    streams: Vec<RecStream>,
+    #[br(temp)]
+    #[bw(calc = projectiles.len() as u32)]
+    projectile_count: u32,
+    #[br(count = projectile_count)]
+    projectiles: Vec<RecProjectile>,
+}
+
+#[binrw]
+#[derive(Clone)]
+struct RecStream {
+    forward_speed: f32,
+    back_speed: f32,
+    backwards_time_left: u32,
+    slowed_time_left: u32,
+    stopped_time_left: u32,
+    #[br(temp)]
+    #[bw(calc = balls.len() as u32)]
+    ball_count: u32,
+    #[br(count = ball_count)]
+    balls: Vec<RecBall>,
+}
+
+#[binrw]
+#[derive(Clone, Copy)]
+struct RecBall {
+    id: u32,
+    color: u8,
+    effect: u8,
+    is_reachable: u8,
+    distance_along_path: f32,
+    coordinates: Point,
+}
+
+#[binrw]
+#[derive(Clone, Copy)]
+struct RecProjectile {
+    ball: RecBall,
+    position: Point,
+    direction: Point,
+    speed: f32,
+}
+
+#[binrw]
+#[derive(Clone, Copy)]
+struct RecFrog {
+    location: Point,
+    destination: Point,
+    ball_exit_speed: f32,
+    active_ball: RecBall,
+    next_ball: RecBall,
+}
+
+#[binrw]
+#[derive(Clone)]
+struct RecLevel {
+    id: RecString,
+    display_name: RecString,
+    background: RecString,
+    frog_position: Point,
+    frog_type: RecFrogType,
+}
+
+#[binrw]
+#[derive(Clone)]
+struct RecFrogType {
+    kind: u8, // 0: static, 1: jumps between the positions, 2: slides between the two positions
+    #[br(temp)]
+    #[bw(calc = positions.len() as u32)]
+    position_count: u32,
+    #[br(count = position_count)]
+    positions: Vec<Point>,
+}
+
+#[binrw]
+#[derive(Clone)]
+struct RecString {
+    #[br(temp)]
+    #[bw(calc = bytes.len() as u32)]
+    len: u32,
+    #[br(count = len)]
+    bytes: Vec<u8>,
+}
+
+#[binrw]
+#[derive(Clone, Copy)]
+struct RecMove {
+    kind: u8, // 0: nothing, 1: shoot, 2: swap then shoot, 3: move the frog
+    target: Point,
+}
+
+impl From<&Ball> for RecBall {
+    fn from(ball: &Ball) -> Self {
+        Self {
+            id: ball.id,
+            color: color_to_number(ball.color),
+            effect: effect_to_number(ball.effect),
+            is_reachable: ball.is_reachable as u8,
+            distance_along_path: ball.distance_along_path,
+            coordinates: ball.coordinates,
+        }
+    }
+}
+
+// The numbers written for colors and effects, which the recordings already made depend on
+fn color_to_number(color: BallColor) -> u8 {
+    match color {
+        BallColor::Blue => 0,
+        BallColor::Yellow => 1,
+        BallColor::Red => 2,
+        BallColor::Green => 3,
+        BallColor::Purple => 4,
+        BallColor::White => 5,
+    }
+}
+
+fn number_to_color(num: u8) -> BallColor {
+    match num {
+        0 => BallColor::Blue,
+        1 => BallColor::Yellow,
+        2 => BallColor::Red,
+        3 => BallColor::Green,
+        4 => BallColor::Purple,
+        _ => BallColor::White,
+    }
+}
+
+fn effect_to_number(effect: BallEffect) -> u8 {
+    match effect {
+        BallEffect::None => 0,
+        BallEffect::Slow => 1,
+        BallEffect::Reverse => 2,
+        BallEffect::Bomb => 3,
+        BallEffect::Visor => 4,
+    }
+}
+
+fn number_to_effect(num: u8) -> BallEffect {
+    match num {
+        1 => BallEffect::Slow,
+        2 => BallEffect::Reverse,
+        3 => BallEffect::Bomb,
+        4 => BallEffect::Visor,
+        _ => BallEffect::None,
+    }
+}
+
+impl RecBall {
+    fn to_ball(self) -> Ball {
+        Ball {
+            coordinates: self.coordinates,
+            is_reachable: self.is_reachable != 0,
+            color: number_to_color(self.color),
+            effect: number_to_effect(self.effect),
+            distance_along_path: self.distance_along_path,
+            id: self.id,
+        }
+    }
+}
+
+impl From<&Projectile> for RecProjectile {
+    fn from(projectile: &Projectile) -> Self {
+        Self {
+            ball: RecBall::from(&projectile.ball),
+            position: projectile.position,
+            direction: projectile.direction,
+            speed: projectile.speed,
+        }
+    }
+}
+
+impl RecProjectile {
+    fn to_projectile(self) -> Projectile {
+        Projectile {
+            ball: self.ball.to_ball(),
+            position: self.position,
+            direction: self.direction,
+            speed: self.speed,
+        }
+    }
+}
+
+impl From<&str> for RecString {
+    fn from(string: &str) -> Self {
+        Self {
+            bytes: string.as_bytes().to_vec(),
+        }
+    }
+}
+
+impl RecString {
+    fn to_text(&self) -> String {
+        String::from_utf8_lossy(&self.bytes).into_owned()
+    }
+}
+
+impl From<&FrogType> for RecFrogType {
+    fn from(frog_type: &FrogType) -> Self {
+        let (kind, positions) = match frog_type {
+            FrogType::Static(position) => (0, vec![*position]),
+            FrogType::Jumper(positions) => (1, positions.clone()),
+            FrogType::Slider(start, end) => (2, vec![*start, *end]),
+        };
+        Self { kind, positions }
+    }
+}
+
+impl RecFrogType {
+    fn to_frog_type(&self, frog_position: Point) -> FrogType {
+        match (self.kind, &self.positions[..]) {
+            (1, positions) if !positions.is_empty() => FrogType::Jumper(positions.to_vec()),
+            (2, &[start, end]) => FrogType::Slider(start, end),
+            (_, &[position, ..]) => FrogType::Static(position),
+            _ => FrogType::Static(frog_position),
+        }
+    }
+}
+
+impl From<BotMove> for RecMove {
+    fn from(bot_move: BotMove) -> Self {
+        match bot_move {
+            BotMove::Nothing => Self {
+                kind: 0,
+                target: Point { x: 0., y: 0. },
+            },
+            BotMove::Shoot(target) => Self { kind: 1, target },
+            BotMove::SwapShoot(target) => Self { kind: 2, target },
+            BotMove::MoveFrog(target) => Self { kind: 3, target },
+        }
+    }
+}
+
+impl RecMove {
+    fn to_bot_move(self) -> BotMove {
+        match self.kind {
+            1 => BotMove::Shoot(self.target),
+            2 => BotMove::SwapShoot(self.target),
+            3 => BotMove::MoveFrog(self.target),
+            _ => BotMove::Nothing,
+        }
+    }
+}
+
+// Writes what the bot observed and did, so that it can be replayed later
+pub struct Recorder<W: Write + Seek = BufWriter<File>> {
+    writer: W,
+    curves: Vec<Arc<Vec<Point>>>, // Last curve written for each stream, shared with the state
+    level: Option<(String, FrogType)>, // Id and frog of the last level written
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>) -> BinResult<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek> Recorder<W> {
+    pub fn new(mut writer: W) -> BinResult<Self> {
+        RecHeader { version: VERSION }.write(&mut writer)?;
+        Ok(Self {
+            writer,
+            curves: vec![],
+            level: None,
+        })
+    }
+
+    // Record one observation of the game, and the move the bot made from it if any
+    pub fn record(
+        &mut self,
+        state: &GameState,
+        frog: Option<Frog>,
+        bot_move: Option<BotMove>,
+    ) -> BinResult<()> {
+        // A curve is only loaded again when it changes, so the same one is still shared
+        self.curves
+            .resize_with(state.streams.len(), Default::default);
+        for (i, stream) in state.streams.iter().enumerate() {
+            if Arc::ptr_eq(&self.curves[i], &stream.curve.points) {
+                continue;
+            }
+
+            let points = stream
+                .curve
+                .points
+                .iter()
+                .enumerate()
+                .map(|(dist, &point)| RecCurvePoint {
+                    point,
+                    is_tunnel: stream.curve.get_tunnel_at_dist(dist as f32) as u8,
+                })
+                .collect();
+            Record::Curve(RecCurve {
+                stream: i as u8,
+                points,
+            })
+            .write(&mut self.writer)?;
+            self.curves[i] = stream.curve.points.clone();
+        }
+
+        let level_key = state
+            .level
+            .as_ref()
+            .map(|level| (level.id.clone(), level.frog_type.clone()));
+        if let Some(level) = state.level.as_ref().filter(|_| level_key != self.level) {
+            Record::Level(RecLevel {
+                id: level.id.as_str().into(),
+                display_name: level.display_name.as_str().into(),
+                background: level.background.as_str().into(),
+                frog_position: level.frog_position,
+                frog_type: RecFrogType::from(&level.frog_type),
+            })
+            .write(&mut self.writer)?;
+        }
+        self.level = level_key;
+
+        let streams = state
+            .streams
+            .iter()
+            .map(|stream| RecStream {
+                forward_speed: stream.forward_speed,
+                back_speed: stream.back_speed,
+                backwards_time_left: stream.backwards_time_left,
+                slowed_time_left: stream.slowed_time_left,
+                stopped_time_left: stream.stopped_time_left,
+                balls: stream.balls.iter().map(RecBall::from).collect(),
+            })
+            .collect();
+        Record::State(RecState {
+            time_ms: state.time.as_millis() as u32,
+            streams,
+            projectiles: state.projectiles.iter().map(RecProjectile::from).collect(),
+        })
+        .write(&mut self.writer)?;
+
+        if let Some(frog) = frog {
+            Record::Frog(RecFrog {
+                location: frog.location,
+                destination: frog.destination,
+                ball_exit_speed: frog.ball_exit_speed,
+                active_ball: RecBall::from(&frog.active_ball),
+                next_ball: RecBall::from(&frog.next_ball),
+            })
+            .write(&mut self.writer)?;
+        }
+
+        if let Some(bot_move) = bot_move {
+            Record::Move(RecMove::from(bot_move)).write(&mut self.writer)?;
+        }
+
+        // Keep the file usable if the program stops abruptly
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub struct ReplayFrame {
+    pub state: GameState,
+    pub frog: Option<Frog>,
+    pub bot_move: Option<BotMove>,
+}
+
+struct ReplayEntry {
+    curve_set: usize, // Index of the curves the balls followed
+    level: Option<usize>,
+    state: RecState,
+    frog: Option<RecFrog>,
+    bot_move: Option<RecMove>,
+}
+
+// A recording loaded in memory. Curves are shared between frames
+// since they are large and rarely change
+pub struct Replay {
+    curve_sets: Vec<Vec<Curve>>,
+    levels: Vec<RecLevel>,
+    entries: Vec<ReplayEntry>,
+}
+
+impl Replay {
+    pub fn load(path: impl AsRef<Path>) -> BinResult<Self> {
+        let data = std::fs::read(path)?;
+        Self::read_from(&mut Cursor::new(data))
+    }
+
+    pub fn read_from<R: Read + Seek>(reader: &mut R) -> BinResult<Self> {
+        RecHeader::read(reader)?;
+
+        let mut replay = Self {
+            curve_sets: vec![vec![]],
+            levels: vec![],
+            entries: vec![],
+        };
+        loop {
+            // A truncated last record is expected if the recorder was interrupted
+            let record = match Record::read(reader) {
+                Ok(record) => record,
+                Err(err) if err.is_eof() => break,
+                Err(err) => return Err(err),
+            };
+
+            match record {
+                Record::Curve(curve) => {
+                    let mut curves = replay.curve_sets.last().unwrap().clone();
+                    let stream = curve.stream as usize;
+                    if curves.len() <= stream {
+                        curves.resize(stream + 1, Curve::new());
+                    }
+                    curves[stream] = Curve::from_points(
+                        curve.points.iter().map(|p| p.point).collect(),
+                        curve.points.iter().map(|p| p.is_tunnel != 0).collect(),
+                    );
+                    replay.curve_sets.push(curves);
+                }
+                Record::Level(level) => replay.levels.push(level),
+                Record::State(state) => replay.entries.push(ReplayEntry {
+                    curve_set: replay.curve_sets.len() - 1,
+                    level: replay.levels.len().checked_sub(1),
+                    state,
+                    frog: None,
+                    bot_move: None,
+                }),
+                Record::Frog(frog) => {
+                    if let Some(entry) = replay.entries.last_mut() {
+                        entry.frog = Some(frog);
+                    }
+                }
+                Record::Move(bot_move) => {
+                    if let Some(entry) = replay.entries.last_mut() {
+                        entry.bot_move = Some(bot_move);
+                    }
+                }
+            }
+        }
+
+        Ok(replay)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn frame(&self, idx: usize) -> ReplayFrame {
+        let entry = &self.entries[idx];
+        let curves = &self.curve_sets[entry.curve_set];
+
+        let streams = entry
+            .state
+            .streams
+            .iter()
+            .enumerate()
+            .map(|(i, stream)| BallStream {
+                balls: stream.balls.iter().map(|ball| ball.to_ball()).collect(),
+                curve: curves.get(i).cloned().unwrap_or_else(Curve::new),
+                forward_speed: stream.forward_speed,
+                back_speed: stream.back_speed,
+                backwards_time_left: stream.backwards_time_left,
+                slowed_time_left: stream.slowed_time_left,
+                stopped_time_left: stream.stopped_time_left,
+                physics: Physics::default(),
+            })
+            .collect();
+
+        ReplayFrame {
+            state: GameState {
+                streams,
+                projectiles: entry
+                    .state
+                    .projectiles
+                    .iter()
+                    .map(|projectile| projectile.to_projectile())
+                    .collect(),
+                time: Duration::from_millis(entry.state.time_ms.into()),
+                level: entry.level.map(|idx| {
+                    let level = &self.levels[idx];
+                    Arc::new(Level {
+                        id: level.id.to_text(),
+                        display_name: level.display_name.to_text(),
+                        frog_position: level.frog_position,
+                        frog_type: level.frog_type.to_frog_type(level.frog_position),
+                        background: level.background.to_text(),
+                        curves: curves.clone(),
+                    })
+                }),
+            },
+            frog: entry.frog.map(|frog| Frog {
+                location: frog.location,
+                destination: frog.destination,
+                active_ball: frog.active_ball.to_ball(),
+                next_ball: frog.next_ball.to_ball(),
+                ball_exit_speed: frog.ball_exit_speed,
+            }),
+            bot_move: entry.bot_move.map(RecMove::to_bot_move),
+        }
+    }
+}
+
+#[test]
+fn recording_round_trip() {
+    let ball = |id: u32, color: BallColor, distance_along_path: f32| Ball {
+        coordinates: Point {
+            x: distance_along_path,
+            y: 10.,
+        },
+        is_reachable: id != 1,
+        color,
+        effect: BallEffect::Reverse,
+        distance_along_path,
+        id,
+    };
+    let points = (0..100)
+        .map(|x| Point {
+            x: x as f32,
+            y: 10.,
+        })
+        .collect();
+    let curve = Curve::from_points(points, vec![false; 100]);
+    let mut state = GameState {
+        streams: vec![BallStream {
+            balls: vec![ball(0, BallColor::Red, 10.), ball(1, BallColor::White, 42.)],
+            curve: curve.clone(),
+            ..BallStream::new()
+        }],
+        projectiles: vec![],
+        time: Duration::from_millis(1700),
+        level: Some(Arc::new(Level {
+            id: "spiral".into(),
+            display_name: "Spiral".into(),
+            frog_position: Point { x: 50., y: 50. },
+            frog_type: FrogType::Jumper(vec![Point { x: 50., y: 50. }, Point { x: 90., y: 50. }]),
+            background: "levels/spiral/spiral.jpg".into(),
+            curves: vec![curve],
+        })),
+    };
+    let frog = Frog {
+        location: Point { x: 50., y: 50. },
+        destination: Point { x: 90., y: 50. },
+        active_ball: ball(2, BallColor::Green, 0.),
+        next_ball: ball(3, BallColor::Blue, 0.),
+        ball_exit_speed: 10.,
+    };
+
+    state.projectiles.push(Projectile::new(
+        &frog,
+        frog.active_ball,
+        Point { x: 42., y: 10. },
+    ));
+
+    let mut file = Cursor::new(vec![]);
+    let mut recorder = Recorder::new(&mut file).unwrap();
+    recorder
+        .record(
+            &state,
+            Some(frog),
+            Some(BotMove::Shoot(Point { x: 42., y: 10. })),
+        )
+        .unwrap();
+    state.time += FRAME_DURATION;
+    state.streams[0].balls.pop();
+    recorder.record(&state, None, None).unwrap();
+    // The curve and the level did not change so they were only written once,
+    // at 9 bytes per point for the curve
+    assert!(file.get_ref().len() < 2 * 9 * 100);
+
+    file.set_position(0);
+    let replay = Replay::read_from(&mut file).unwrap();
+    assert_eq!(2, replay.len());
+
+    let first = replay.frame(0);
+    assert_eq!(
+        state.streams[0].curve.points,
+        first.state.streams[0].curve.points
+    );
+    assert_eq!(
+        vec![ball(0, BallColor::Red, 10.), ball(1, BallColor::White, 42.)],
+        first.state.streams[0].balls
+    );
+    assert_eq!(frog.next_ball, first.frog.unwrap().next_ball);
+    assert_eq!(frog.destination, first.frog.unwrap().destination);
+    assert_eq!(1, first.state.projectiles.len());
+    assert_eq!(
+        state.projectiles[0].direction,
+        first.state.projectiles[0].direction
+    );
+    assert!(matches!(first.bot_move, Some(BotMove::Shoot(Point { x, .. })) if x == 42.));
+
+    let second = replay.frame(1);
+    assert_eq!(state.time, second.state.time);
+    assert_eq!(state.streams[0].balls, second.state.streams[0].balls);
+    assert_eq!(100, second.state.streams[0].curve.points.len());
+    assert!(second.frog.is_none() && second.bot_move.is_none());
+    let level = second.state.level.unwrap();
+    assert_eq!("Spiral", level.display_name);
+    assert_eq!(state.level.as_ref().unwrap().frog_type, level.frog_type);
+    assert_eq!(100, level.curves[0].points.len());
+}
+
+#[test]
+fn colors_and_effects_round_trip() {
+    for color in BallColor::ALL {
+        assert_eq!(color, number_to_color(color_to_number(color)));
+    }
+    use BallEffect::*;
+    for effect in [None, Slow, Reverse, Bomb, Visor] {
+        assert_eq!(effect, number_to_effect(effect_to_number(effect)));
+    }
+}